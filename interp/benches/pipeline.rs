@@ -0,0 +1,61 @@
+//! Benchmarks for the whole lex, parse and evaluate pipeline, over
+//! expressions of varying size and nesting, and for repeatedly evaluating
+//! one parsed expression either by walking the tree or as bytecode.
+
+#[macro_use]
+extern crate criterion;
+extern crate interp;
+
+use criterion::{black_box, Criterion};
+use interp::{compile, evaluate, lex, parse, run, Calculator, Value};
+
+/// `( 1 + ( 1 + ( ... ) ) )`, nested `depth` parentheses deep.
+fn deep(depth: usize) -> String {
+    let mut expression = String::from("1");
+    for _ in 0..depth {
+        expression = format!("( 1 + {} )", expression);
+    }
+    expression
+}
+
+/// `1 + 2 * 3 - 4 + ...` with `width` operands and no parentheses.
+fn wide(width: usize) -> String {
+    let operators = ["+", "*", "-", "%"];
+    let mut expression = String::from("1");
+    for i in 1..width {
+        expression.push_str(&format!(" {} {}", operators[i % operators.len()], i % 7 + 1));
+    }
+    expression
+}
+
+fn pipeline(c: &mut Criterion) {
+    let calculator = Calculator::new();
+    let cases = vec![
+        ("shallow", String::from("1 + 2 * 3")),
+        ("functions", String::from("sum ( 1 , 2 , 3 ) * clamp ( 7 , 1 , 5 ) ^ 2")),
+        ("deep", deep(150)),
+        ("wide", wide(1000)),
+    ];
+    for (name, expression) in cases {
+        c.bench_function(name, |b| b.iter(|| {
+            let expr = parse(lex(black_box(&expression))).ok().unwrap();
+            evaluate(&expr, &calculator).ok().unwrap()
+        }));
+    }
+}
+
+fn compiled(c: &mut Criterion) {
+    let mut calculator = Calculator::new();
+    calculator.vars.insert(String::from("x"), Value::Int(3));
+    let expr = parse(lex("( x + 1 ) * ( x - 2 ) ^ 2 % 7 + x * x")).ok().unwrap();
+    let program = compile(&expr).ok().unwrap();
+    c.bench_function("tree-walking", |b| b.iter(|| {
+        evaluate(black_box(&expr), &calculator).ok().unwrap()
+    }));
+    c.bench_function("bytecode", |b| b.iter(|| {
+        run(black_box(&program), &[3]).ok().unwrap()
+    }));
+}
+
+criterion_group!(benches, pipeline, compiled);
+criterion_main!(benches);
@@ -0,0 +1,202 @@
+use std::convert::TryFrom;
+
+use calculator::Settings;
+use eval::{checked_pow, factorial, EvalError};
+use parser::Expr;
+use value::{IntType, Value};
+
+/// One instruction of a compiled program. Each pops its operands from the
+/// stack and pushes its result.
+enum Op {
+    Push(i32),
+    /// Push the value in the given slot of the environment.
+    Load(usize),
+    Add, Sub, Mul, Div, Rem, Pow,
+    Neg,
+    Factorial,
+}
+
+/// An expression compiled for a stack machine, which can be run many
+/// times with different values for its variables.
+pub struct Program {
+    ops: Vec<Op>,
+    vars: Vec<String>,
+}
+
+impl Program {
+    /// The names of the variables, in the order their values are given
+    /// to `run`.
+    pub fn vars(&self) -> &[String] {
+        &self.vars
+    }
+}
+
+
+/// Compile an expression to a program. Only 32-bit integer arithmetic on
+/// literals and variables is supported, and the program behaves exactly
+/// as `evaluate` does in integer mode.
+pub fn compile(expr: &Expr) -> Result<Program, EvalError> {
+    let mut program = Program { ops: Vec::new(), vars: Vec::new() };
+    emit(expr, &mut program)?;
+    Ok(program)
+}
+
+
+fn emit(expr: &Expr, program: &mut Program) -> Result<(), EvalError> {
+    use Expr::*;
+    let (l, r, op) = match *expr {
+        Number(n) => {
+            program.ops.push(Op::Push(n));
+            return Ok(());
+        },
+        Var(ref name) => {
+            let slot = match program.vars.iter().position(|var| var == name) {
+                Some(slot) => slot,
+                None => {
+                    program.vars.push(name.clone());
+                    program.vars.len() - 1
+                },
+            };
+            program.ops.push(Op::Load(slot));
+            return Ok(());
+        },
+        Negate(ref operand) => {
+            emit(operand, program)?;
+            program.ops.push(Op::Neg);
+            return Ok(());
+        },
+        Factorial(ref operand) => {
+            emit(operand, program)?;
+            program.ops.push(Op::Factorial);
+            return Ok(());
+        },
+        Plus(ref l, ref r) => (l, r, Op::Add),
+        Minus(ref l, ref r) => (l, r, Op::Sub),
+        Times(ref l, ref r) => (l, r, Op::Mul),
+        Divide(ref l, ref r) => (l, r, Op::Div),
+        Modulo(ref l, ref r) => (l, r, Op::Rem),
+        Pow(ref l, ref r) => (l, r, Op::Pow),
+        Float(x) => return Err(EvalError::FloatInIntegerMode(x)),
+        _ => return Err(EvalError::NotCompilable),
+    };
+    emit(l, program)?;
+    emit(r, program)?;
+    program.ops.push(op);
+    Ok(())
+}
+
+
+/// Evaluate `expr` once for each of `values` of the variable `var`, as for
+/// plotting. The expression is compiled once, so it is limited to what
+/// `compile` accepts, and `var` must be its only variable.
+pub fn evaluate_vectorized(expr: &Expr, var: &str, values: &[i32]) -> Vec<Result<i32, EvalError>> {
+    let program = match compile(expr) {
+        Ok(program) => program,
+        Err(error) => return values.iter().map(|_| Err(error.clone())).collect(),
+    };
+    if let Some(other) = program.vars.iter().find(|name| *name != var) {
+        return values.iter().map(|_| Err(EvalError::UnknownVariable(other.clone()))).collect();
+    }
+    // The program has at most one variable, and it is `var`.
+    let mut env = vec![0; program.vars.len()];
+    values.iter().map(|&value| {
+        if let Some(slot) = env.first_mut() {
+            *slot = value;
+        }
+        run(&program, &env)
+    }).collect()
+}
+
+
+/// Run a compiled program. `env` holds the value of each variable, in the
+/// order given by `Program::vars`.
+pub fn run(program: &Program, env: &[i32]) -> Result<i32, EvalError> {
+    use EvalError::*;
+    if env.len() < program.vars.len() {
+        return Err(UnknownVariable(program.vars[env.len()].clone()));
+    }
+    let overflow = || Overflow(IntType::I32);
+    let mut stack: Vec<i32> = Vec::with_capacity(program.ops.len());
+    for op in &program.ops {
+        let result = match *op {
+            Op::Push(n) => n,
+            Op::Load(slot) => env[slot],
+            Op::Neg => stack.pop().unwrap().checked_neg().ok_or_else(overflow)?,
+            Op::Factorial => {
+                let operand = Value::Int(stack.pop().unwrap());
+                match factorial(operand, &Settings::default())? {
+                    Value::Int(n) => n,
+                    _ => return Err(overflow()),
+                }
+            },
+            ref op => {
+                let r = i64::from(stack.pop().unwrap());
+                let l = i64::from(stack.pop().unwrap());
+                let result = match *op {
+                    Op::Add => l.checked_add(r),
+                    Op::Sub => l.checked_sub(r),
+                    Op::Mul => l.checked_mul(r),
+                    Op::Div | Op::Rem if r == 0 => return Err(DivisionByZero),
+                    Op::Div => l.checked_div(r),
+                    Op::Rem => l.checked_rem(r),
+                    Op::Pow if r < 0 => return Err(NegativeExponent),
+                    _ => checked_pow(l, r),
+                };
+                result.and_then(|n| i32::try_from(n).ok()).ok_or_else(overflow)?
+            },
+        };
+        stack.push(result);
+    }
+    Ok(stack.pop().unwrap())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use calculator::Calculator;
+    use eval::{evaluate_env, Env};
+    use lexer::lex;
+    use parser::parse;
+
+    #[test]
+    fn compiled_programs_agree_with_evaluate() {
+        let calculator = Calculator::new();
+        let inputs = ["1 + 2 * 3", "x ^ 2 - 3 * x + 2", "( x - 4 ) * - x % 5", "10 / ( x - 3 )",
+                      "x ! - 2 ^ 3 ^ 2", "2147483647 + x", "20 - x - x - x"];
+        for input in inputs {
+            let expr = parse(lex(input)).ok().expect("the input should parse");
+            let program = compile(&expr).ok().expect("the expression should compile");
+            for x in -3..=12 {
+                let vars = program.vars().iter().map(|var| (var.clone(), Value::Int(x)));
+                let env = Env { vars: vars.collect(), ..Env::default() };
+                let compiled = run(&program, &vec![x; program.vars().len()]);
+                match (compiled, evaluate_env(&expr, &calculator, &env)) {
+                    (Ok(n), Ok(Value::Int(m))) => assert_eq!(n, m, "{} at x = {}", input, x),
+                    (Err(_), Err(_)) => {},
+                    _ => panic!("{} at x = {} differs", input, x),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn only_integer_arithmetic_compiles() {
+        let compiled = |input| compile(&parse(lex(input)).ok().expect("the input should parse"));
+        assert!(matches!(compiled("1.5 + x"), Err(EvalError::FloatInIntegerMode(_))));
+        assert!(matches!(compiled("abs ( x )"), Err(EvalError::NotCompilable)));
+    }
+
+    #[test]
+    fn a_quadratic_over_a_range() {
+        let expr = parse(lex("x ^ 2 - 3 * x + 2")).ok().expect("the input should parse");
+        let values: Vec<i32> = (-1..=4).collect();
+        let results: Vec<i32> = evaluate_vectorized(&expr, "x", &values).into_iter()
+            .map(|result| result.ok().expect("each value should evaluate"))
+            .collect();
+        assert_eq!(results, vec![6, 2, 0, 0, 2, 6]);
+        let other = parse(lex("x + y")).ok().expect("the input should parse");
+        assert!(matches!(evaluate_vectorized(&other, "x", &[1])[0],
+                         Err(EvalError::UnknownVariable(ref name)) if name == "y"));
+    }
+}
@@ -0,0 +1,1378 @@
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::collections::LinkedList;
+use std::fs;
+use std::time::Instant;
+
+use serde::Deserialize;
+
+use bytecode::{compile, run};
+use eval::{constant, evaluate, sample, trace, EvalError, Warning};
+use lexer::{lex, lex_with, Token};
+use messages::{eval_message_in, position_message_in, syntax_message_in, warning_message_in,
+               Language};
+use parser::{lexical_error, operator_help, parse_located, parse_rpn_located, parse_signature,
+             precedence_notes, to_grouped, to_sexpr, to_tree, Expr, Function, Statement,
+             SyntaxError};
+use value::{to_words, IntType, Value, MAX_PRECISION};
+
+/// The operators which `:operators` can switch on and off.
+pub(crate) const OPERATORS: &str = "+-*/%^!";
+
+/// The largest recursion limit which `:recursion-limit` and the
+/// configuration file accept. Every call takes stack, so beyond this a
+/// runaway recursion could overflow the stack. `main` runs the session
+/// with stack enough for this many calls of a deeply nested function.
+pub const MAX_RECURSION_LIMIT: usize = 500;
+
+/// The number of samples `:plot` takes, one per column, and the number of
+/// rows it draws them in.
+const PLOT_WIDTH: usize = 60;
+const PLOT_HEIGHT: usize = 15;
+
+/// Which kind of arithmetic the evaluator performs.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Numbers {
+    /// 32-bit integer arithmetic. Decimal literals are rejected.
+    #[default]
+    Integer,
+    /// 64-bit floating-point arithmetic. Integer literals are promoted.
+    Float,
+    /// Integer literals are integers and decimal literals are floats. An
+    /// operation on two integers is integer arithmetic, and one with a
+    /// float operand promotes the other to a float: `4 / 2` is 2 but
+    /// `1 + 2.0` is 3.0.
+    Mixed,
+}
+
+/// What to do when a floating-point operation produces `inf` or `nan`.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NanMode {
+    /// Report an error.
+    #[default]
+    Error,
+    /// Keep the non-finite value and print it as `inf`, `-inf` or `nan`.
+    Allow,
+}
+
+/// How input expressions are written.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Syntax {
+    /// Ordinary infix notation, e.g. `( 1 + 2 ) * 3`.
+    #[default]
+    Infix,
+    /// Reverse Polish (postfix) notation, e.g. `1 2 + 3 *`.
+    Rpn,
+}
+
+/// How the results of integer division are shown.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Quotient {
+    /// Just the quotient, e.g. `7 / 2` is `3`.
+    #[default]
+    Truncate,
+    /// `3 remainder 1`. The remainder has the sign of the dividend, as
+    /// with `%`.
+    Remainder,
+    /// The mixed number `3 1/2`.
+    Mixed,
+    /// `quotient 3, remainder 1`, even if the remainder is 0, and `%`
+    /// shows the quotient as well as the remainder.
+    DivMod,
+}
+
+/// Whether integer results are shown as bit patterns, set with `:bits`.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Bits {
+    /// As signed decimal numbers.
+    #[default]
+    Off,
+    /// As binary, e.g. `0b11111111` for `-1i8`.
+    Bin,
+    /// As hexadecimal, e.g. `0xff` for `-1i8`.
+    Hex,
+}
+
+impl Bits {
+    /// The two's complement bit pattern of `n`, which has the width
+    /// `width`, read as an unsigned number and padded to the full width.
+    /// `None` if bit patterns are off.
+    fn pattern(self, n: i64, width: IntType) -> Option<String> {
+        let bits = width.bits() as usize;
+        let unsigned = n as u64 & (u64::MAX >> (64 - bits));
+        match self {
+            Bits::Off => None,
+            Bits::Bin => Some(format!("0b{:01$b}", unsigned, bits)),
+            Bits::Hex => Some(format!("0x{:01$x}", unsigned, bits / 4)),
+        }
+    }
+}
+
+/// How float results are shown.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    /// Ordinary decimal notation, e.g. `602000000000`.
+    #[default]
+    Fixed,
+    /// Scientific notation, e.g. `6.02e11`.
+    Sci,
+    /// Scientific notation for magnitudes of at least 1e16 or below 1e-4,
+    /// and decimal notation otherwise.
+    Auto,
+}
+
+impl Format {
+    /// Whether to show `x` in scientific notation.
+    fn is_scientific(self, x: f64) -> bool {
+        match self {
+            Format::Fixed => false,
+            Format::Sci => true,
+            Format::Auto => x != 0.0 && (x.abs() >= 1e16 || x.abs() < 1e-4),
+        }
+    }
+}
+
+/// Evaluator settings which can be changed during a session. They may
+/// also be read from a configuration file, where the names are those
+/// listed by `:mode` and any which are missing keep their defaults.
+#[derive(Clone, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct Settings {
+    pub numbers: Numbers,
+    pub nan: NanMode,
+    pub syntax: Syntax,
+    /// How deeply calls to user-defined functions may nest.
+    pub recursion_limit: usize,
+    /// Print nothing but bare results on stdout; errors go to stderr.
+    pub raw: bool,
+    /// Read undefined variables as 0, with a warning, instead of failing.
+    pub lenient_vars: bool,
+    /// How many decimal places to show floats with. `None`, set with
+    /// `:precision auto`, shows as many as are needed to identify the value
+    /// exactly. At most `MAX_PRECISION`.
+    pub precision: Option<usize>,
+    /// The decimal point in input, `.` or `,`. See `lex_with`.
+    pub decimal_sep: char,
+    /// The symbols of the operators which may be used, out of `+-*/%^!`.
+    /// Unary `+` and `-` count as their binary forms.
+    pub operators: String,
+    pub quotient: Quotient,
+    pub format: Format,
+    pub bits: Bits,
+    /// Reduce every integer result modulo this, which is at least 2. See
+    /// `evaluate_env`.
+    pub modulus: Option<i32>,
+    /// Wrap every integer result to this many bits, from 1 to 30, as
+    /// unsigned machine arithmetic does. Only one of this and `modulus` is
+    /// set at once. See `evaluate_env`.
+    pub ring: Option<u32>,
+    /// The language of error and warning messages.
+    pub language: Language,
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings {
+            numbers: Numbers::default(),
+            nan: NanMode::default(),
+            syntax: Syntax::default(),
+            recursion_limit: 200,
+            raw: false,
+            lenient_vars: false,
+            precision: None,
+            decimal_sep: '.',
+            operators: String::from(OPERATORS),
+            quotient: Quotient::default(),
+            format: Format::default(),
+            bits: Bits::default(),
+            modulus: None,
+            ring: None,
+            language: Language::default(),
+        }
+    }
+}
+
+impl Settings {
+    /// Describe every setting, one `name: value` pair per line.
+    pub fn describe(&self) -> String {
+        let numbers = match self.numbers {
+            Numbers::Integer => "integer",
+            Numbers::Float => "float",
+            Numbers::Mixed => "mixed",
+        };
+        let nan = match self.nan {
+            NanMode::Error => "error",
+            NanMode::Allow => "allow",
+        };
+        let syntax = match self.syntax {
+            Syntax::Infix => "infix",
+            Syntax::Rpn => "rpn",
+        };
+        let raw = if self.raw { "on" } else { "off" };
+        let lenient_vars = if self.lenient_vars { "on" } else { "off" };
+        let quotient = match self.quotient {
+            Quotient::Truncate => "truncate",
+            Quotient::Remainder => "remainder",
+            Quotient::Mixed => "mixed",
+            Quotient::DivMod => "divmod",
+        };
+        let format = match self.format {
+            Format::Fixed => "fixed",
+            Format::Sci => "sci",
+            Format::Auto => "auto",
+        };
+        let bits = match self.bits {
+            Bits::Off => "off",
+            Bits::Bin => "bin",
+            Bits::Hex => "hex",
+        };
+        let modulus = match self.modulus {
+            Some(modulus) => modulus.to_string(),
+            None => String::from("off"),
+        };
+        let ring = match self.ring {
+            Some(width) => width.to_string(),
+            None => String::from("off"),
+        };
+        let precision = match self.precision {
+            Some(precision) => precision.to_string(),
+            None => String::from("auto"),
+        };
+        format!("numbers: {}\nnan: {}\nsyntax: {}\nrecursion-limit: {}\nraw: {}\n\
+                 lenient-vars: {}\nprecision: {}\ndecimal-sep: {}\noperators: {}\nquotient: {}\n\
+                 format: {}\nbits: {}\nmodulus: {}\nring: {}\nlanguage: {}",
+                numbers, nan, syntax, self.recursion_limit, raw, lenient_vars, precision,
+                self.decimal_sep, self.operators, quotient, format, bits, modulus, ring,
+                self.language)
+    }
+
+    /// What integer results are reduced modulo: the modulus, or two to the
+    /// power of the width of the ring, if either is set.
+    pub fn reduction(&self) -> Option<i32> {
+        self.modulus.or(self.ring.map(|width| 1 << width))
+    }
+}
+
+/// The implementation of a function registered with `register_fn`.
+type NativeFn = Box<dyn Fn(&[Value]) -> Result<Value, EvalError>>;
+
+/// A function registered by library code with `Calculator::register_fn`.
+pub(crate) struct NativeFunction {
+    pub arity: usize,
+    pub call: NativeFn,
+}
+
+/// State held by the REPL across lines of input.
+#[derive(Default)]
+pub struct Calculator {
+    pub settings: Settings,
+    /// The settings the session started with, after any configuration file
+    /// and environment variables, which `:reset` restores.
+    pub startup: Settings,
+    pub results: Vec<Value>,
+    /// The input which produced each result.
+    pub history: Vec<String>,
+    pub functions: HashMap<String, Function>,
+    /// Functions registered with `register_fn`.
+    pub(crate) native: HashMap<String, NativeFunction>,
+    /// Variables assigned during the session. These shadow the constants
+    /// `e` and `pi`, but not the parameters of a function being called.
+    pub vars: HashMap<String, Value>,
+    /// Constants introduced with `:define`, which cannot be reassigned and
+    /// shadow both variables and the built-in constants.
+    pub constants: HashMap<String, Value>,
+    /// The memory register, used by `M+`, `M-`, `MR` and `MC`. It holds 0
+    /// when empty.
+    pub memory: Option<Value>,
+    /// Warnings from evaluation which have not been shown yet.
+    pub warnings: RefCell<Vec<Warning>>,
+    /// Every line of input which succeeded, in order, for `:export`.
+    pub transcript: Vec<String>,
+    /// The value each variable assigned by the latest assignment had
+    /// before it, or `None` if it was new, for `:undo`.
+    undo: Vec<(String, Option<Value>)>,
+}
+
+impl Calculator {
+    pub fn new() -> Calculator {
+        Calculator {
+            settings: Settings::default(),
+            startup: Settings::default(),
+            results: Vec::new(),
+            history: Vec::new(),
+            functions: HashMap::new(),
+            native: HashMap::new(),
+            vars: HashMap::new(),
+            constants: HashMap::new(),
+            memory: None,
+            warnings: RefCell::new(Vec::new()),
+            transcript: Vec::new(),
+            undo: Vec::new(),
+        }
+    }
+
+    /// Make a function implemented in Rust callable as `name`, taking
+    /// `arity` arguments. It is given the evaluated arguments, and is called
+    /// in preference to a built-in function of the same name, but not to
+    /// one defined during the session.
+    pub fn register_fn<F>(&mut self, name: &str, arity: usize, function: F)
+            where F: Fn(&[Value]) -> Result<Value, EvalError> + 'static {
+        self.native.insert(String::from(name),
+                           NativeFunction { arity, call: Box::new(function) });
+    }
+
+    /// Parse an expression written in the current syntax.
+    pub fn parse(&self, input: &str) -> Result<Expr, SyntaxError> {
+        self.parse_tokens(lex_with(input, self.settings.decimal_sep))
+    }
+
+    pub fn parse_tokens(&self, tokens: LinkedList<Token>) -> Result<Expr, SyntaxError> {
+        self.parse_tokens_located(tokens).map_err(|(error, _)| error)
+    }
+
+    /// Parse like `parse_tokens`, giving with an error the index of the
+    /// token it was found at, as `parse_located` does.
+    pub fn parse_tokens_located(&self, tokens: LinkedList<Token>)
+            -> Result<Expr, (SyntaxError, usize)> {
+        for (index, token) in tokens.iter().enumerate() {
+            let symbol = token.to_string();
+            let is_operator = matches!(*token, Token::PLUS | Token::MINUS | Token::TIMES |
+                                               Token::DIVIDE | Token::MODULO | Token::POW |
+                                               Token::NEGATE | Token::IDENTITY | Token::FACTORIAL);
+            if is_operator && !self.settings.operators.contains(symbol.as_str()) {
+                return Err((SyntaxError::OperatorDisabled(symbol), index));
+            }
+        }
+        match self.settings.syntax {
+            Syntax::Infix => parse_located(tokens),
+            Syntax::Rpn => parse_rpn_located(tokens),
+        }
+    }
+
+    /// Parse a line which is an expression, a function definition
+    /// `name ( params ) = body` or an assignment `name = expr`. The body or
+    /// expression is written in the current syntax.
+    pub fn parse_statement(&self, input: &str) -> Result<Statement, SyntaxError> {
+        self.parse_statement_located(input).map_err(|(error, _)| error)
+    }
+
+    /// Parse like `parse_statement`, giving with an error the index of the
+    /// token of `input` it was found at, as `parse_located` does.
+    pub fn parse_statement_located(&self, input: &str)
+            -> Result<Statement, (SyntaxError, usize)> {
+        let mut tokens = lex_with(input, self.settings.decimal_sep);
+        if let Some(located) = lexical_error(&tokens) {
+            return Err(located);
+        }
+        let position = match tokens.iter().position(|token| *token == Token::ASSIGN) {
+            Some(position) => position,
+            None => return Ok(Statement::Expression(self.parse_tokens_located(tokens)?)),
+        };
+        let mut body = tokens.split_off(position);
+        body.pop_front();  // Remove the ASSIGN.
+        // The indices in `input` of the first tokens of `target` and `body`.
+        let mut target_start = 0;
+        let mut start = position + 1;
+        if tokens.len() == 1 {
+            // Every `=` but the last must follow a single name.
+            let mut names = Vec::new();
+            let mut target = tokens;
+            loop {
+                match target.pop_front() {
+                    Some(Token::Identifier(name)) if target.is_empty() => names.push(name),
+                    _ => return Err((SyntaxError::GeneralError, target_start)),
+                }
+                match body.iter().position(|token| *token == Token::ASSIGN) {
+                    Some(position) => {
+                        let rest = body.split_off(position);
+                        target = body;
+                        body = rest;
+                        body.pop_front();  // Remove the ASSIGN.
+                        target_start = start;
+                        start += position + 1;
+                    },
+                    None => break,
+                }
+            }
+            let expr = self.parse_tokens_located(body)
+                .map_err(|(error, index)| (error, start + index))?;
+            return Ok(Statement::Assignment(names, expr));
+        }
+        let (name, params) = parse_signature(tokens)?;
+        let body = self.parse_tokens_located(body)
+            .map_err(|(error, index)| (error, start + index))?;
+        Ok(Statement::Definition(name, Function { params, body }))
+    }
+
+    /// Record the result of `input`, which may then be referred to as `#N`.
+    pub fn record(&mut self, input: &str, value: Value) {
+        self.results.push(value);
+        self.history.push(String::from(input));
+    }
+
+    /// The numbers of the results whose input contains `text`.
+    pub fn search(&self, text: &str) -> Vec<usize> {
+        self.history.iter()
+            .enumerate()
+            .filter(|&(_, input)| input.contains(text))
+            .map(|(index, _)| index + 1)
+            .collect()
+    }
+
+    /// Assign `value` to each of the session variables `names`, as in
+    /// `a = b = 5`. Assigning to the name of a built-in constant is allowed,
+    /// with a warning, and the variable shadows the constant from then on.
+    /// Nothing is assigned if any of the names is a `:define`d constant.
+    pub fn assign(&mut self, names: &[String], value: Value) -> Result<(), EvalError> {
+        if let Some(name) = names.iter().find(|name| self.constants.contains_key(*name)) {
+            return Err(EvalError::ConstantAssignment(name.clone()));
+        }
+        self.undo.clear();
+        for name in names.iter().rev() {
+            if constant(name).is_some() && !self.vars.contains_key(name) {
+                self.warnings.borrow_mut().push(Warning::ShadowsConstant(name.clone()));
+            }
+            let previous = self.vars.insert(name.clone(), value);
+            self.undo.push((name.clone(), previous));
+        }
+        Ok(())
+    }
+
+    /// Evaluate `left` and `right` for `:cmp`, and say whether the results
+    /// are equal, or else what `left - right` is.
+    fn compare_inputs(&self, left: &str, right: &str) -> String {
+        let language = self.settings.language;
+        let (l_expr, r_expr) = match (self.parse(left), self.parse(right)) {
+            (Ok(l_expr), Ok(r_expr)) => (l_expr, r_expr),
+            (Err(error), _) | (_, Err(error)) => return syntax_message_in(error, language),
+        };
+        let (l_value, r_value) = match (evaluate(&l_expr, self), evaluate(&r_expr, self)) {
+            (Ok(l_value), Ok(r_value)) => (l_value, r_value),
+            (Err(error), _) | (_, Err(error)) => {
+                self.warnings.borrow_mut().clear();
+                return eval_message_in(error, language);
+            },
+        };
+        let (l_text, r_text) = (self.display(l_value), self.display(r_value));
+        let message = if l_value.compare(r_value) == Some(Ordering::Equal) {
+            format!("Equal: both are {}.", l_text)
+        } else {
+            match evaluate(&Expr::Minus(Box::new(l_expr), Box::new(r_expr)), self) {
+                Ok(difference) =>
+                    format!("Not equal: {} - {} = {}.", l_text, r_text, self.display(difference)),
+                Err(_) => format!("Not equal: {} and {}.", l_text, r_text),
+            }
+        };
+        self.warnings.borrow_mut().clear();
+        message
+    }
+
+    /// `message`, the reply to a successful `:precision`, with a warning if
+    /// it has no effect yet because the calculator is in integer mode. The
+    /// precision is kept for when float or mixed mode is chosen.
+    fn precision_reply(&self, message: &str) -> String {
+        if self.settings.numbers != Numbers::Integer {
+            return String::from(message);
+        }
+        let warning = warning_message_in(Warning::PrecisionInIntegerMode, self.settings.language);
+        format!("{}\n{}", message, warning)
+    }
+
+    /// Restore the variables assigned by the latest assignment to their
+    /// values before it, and return the text to print. Only one assignment
+    /// can be undone.
+    fn undo(&mut self) -> String {
+        if self.undo.is_empty() {
+            return String::from("Nothing to undo.");
+        }
+        let mut restored = Vec::new();
+        while let Some((name, previous)) = self.undo.pop() {
+            match previous {
+                Some(value) => {
+                    restored.push(format!("{} = {}", name, self.display(value)));
+                    self.vars.insert(name, value);
+                },
+                None => {
+                    restored.push(format!("{} removed", name));
+                    self.vars.remove(&name);
+                },
+            }
+        }
+        restored.join("\n")
+    }
+
+    /// Define a constant from `input`, which is a name followed by an
+    /// expression, and return the text to print.
+    fn define(&mut self, input: &str) -> String {
+        let usage = String::from("Usage: :define NAME EXPR");
+        let mut parts = input.splitn(2, char::is_whitespace);
+        let (name, input) = match (parts.next(), parts.next()) {
+            (Some(name), Some(input)) if !input.trim().is_empty() => (name, input.trim()),
+            _ => return usage,
+        };
+        let mut tokens = lex(name).into_iter();
+        let name = match (tokens.next(), tokens.next()) {
+            (Some(Token::Identifier(name)), Some(Token::EOF)) => name,
+            _ => return usage,
+        };
+        if self.constants.contains_key(&name) {
+            return format!("{} is already defined.", name);
+        }
+        if self.vars.contains_key(&name) {
+            return format!("{} is already a variable.", name);
+        }
+        let result = self.parse(input).map(|expr| evaluate(&expr, self));
+        self.warnings.borrow_mut().clear();
+        match result {
+            Ok(Ok(value)) => {
+                let message = format!("Defined {} = {}.", name, self.display(value));
+                self.constants.insert(name, value);
+                message
+            },
+            Ok(Err(error)) => eval_message_in(error, self.settings.language),
+            Err(error) => syntax_message_in(error, self.settings.language),
+        }
+    }
+
+    /// Run a memory command, `M+` or `M-` to add or subtract the latest
+    /// result, or `MC` to clear the register. Returns `None` if `line` is
+    /// not a memory command.
+    pub fn memory_command(&mut self, line: &str) -> Option<String> {
+        let operation: fn(Box<Expr>, Box<Expr>) -> Expr = match line {
+            "M+" => Expr::Plus,
+            "M-" => Expr::Minus,
+            "MC" => {
+                self.memory = None;
+                return Some(String::from("Memory cleared."));
+            },
+            _ => return None,
+        };
+        if self.results.is_empty() {
+            return Some(String::from("There is no result to use yet."));
+        }
+        let expr = operation(Box::new(Expr::Memory), Box::new(Expr::Previous(self.results.len())));
+        Some(match evaluate(&expr, self) {
+            Ok(value) => {
+                self.memory = Some(value);
+                format!("M = {}", self.display(value))
+            },
+            Err(error) => eval_message_in(error, self.settings.language),
+        })
+    }
+
+    /// Format a result for display with the current precision. Raw output
+    /// leaves off the `~` which marks a rounded float. Under `:bits` an
+    /// integer is shown as its bit pattern, 32 bits wide unless it has a
+    /// type suffix.
+    pub fn display(&self, value: Value) -> String {
+        let pattern = match value {
+            Value::Int(n) => self.settings.bits.pattern(i64::from(n), IntType::I32),
+            Value::Typed(n, width) => self.settings.bits.pattern(n, width),
+            _ => None,
+        };
+        if let Some(pattern) = pattern {
+            return pattern;
+        }
+        let shown = match (value, self.settings.quotient) {
+            (Value::Quotient(q, r, _), Quotient::Remainder) => format!("{} remainder {}", q, r),
+            (Value::Quotient(q, r, _), Quotient::DivMod) |
+            (Value::Remainder(r, q, _), Quotient::DivMod) =>
+                format!("quotient {}, remainder {}", q, r),
+            (Value::Quotient(0, r, d), Quotient::Mixed) =>
+                format!("{}{}/{}", if (r < 0) != (d < 0) { "-" } else { "" },
+                        r.unsigned_abs(), d.unsigned_abs()),
+            (Value::Quotient(q, r, d), Quotient::Mixed) =>
+                format!("{} {}/{}", q, r.unsigned_abs(), d.unsigned_abs()),
+            (Value::Float(x), _) if self.settings.format.is_scientific(x) =>
+                value.display(self.settings.precision, true),
+            // In mixed mode a whole float keeps its `.0`, so that it can be
+            // told apart from an integer.
+            (Value::Float(x), _) if self.settings.numbers == Numbers::Mixed &&
+                                    self.settings.precision.is_none() &&
+                                    x.is_finite() && x.fract() == 0.0 => format!("{}.0", x),
+            _ => value.display(self.settings.precision, false),
+        };
+        if self.settings.raw {
+            shown.trim_end_matches('~').to_string()
+        } else {
+            shown
+        }
+    }
+
+    /// Lex, parse and evaluate `input` `count` times, reporting the last
+    /// result with the total and per-iteration time. The whole pipeline is
+    /// rerun every time, and the result is not added to the session.
+    fn repeat(&self, count: u32, input: &str) -> String {
+        let start = Instant::now();
+        let mut result = String::new();
+        for _ in 0..count {
+            result = match self.parse(input) {
+                Ok(expr) => match evaluate(&expr, self) {
+                    Ok(value) => self.display(value),
+                    Err(error) => eval_message_in(error, self.settings.language),
+                },
+                Err(error) => syntax_message_in(error, self.settings.language),
+            };
+        }
+        let total = start.elapsed();
+        self.warnings.borrow_mut().clear();
+        format!("{}\n{} iterations in {:?} ({:?} each)", result, count, total, total / count)
+    }
+
+    /// Plot `input` as the variable `var` runs from `lo` to `hi`, as a grid
+    /// of `PLOT_WIDTH` samples by `PLOT_HEIGHT` rows, labelled with the
+    /// largest and smallest results and the ends of the range. Samples
+    /// which fail are left as gaps.
+    fn plot(&self, input: &str, var: &str, lo: f64, hi: f64) -> String {
+        let expr = match self.parse(input) {
+            Ok(expr) => expr,
+            Err(error) => return syntax_message_in(error, self.settings.language),
+        };
+        let samples = sample(&expr, var, lo, hi, PLOT_WIDTH, self);
+        self.warnings.borrow_mut().clear();
+        let ys: Vec<f64> = samples.iter().filter_map(|&(_, y)| y).collect();
+        if ys.is_empty() {
+            return format!("{} could not be evaluated anywhere in the range.", input);
+        }
+        let top = ys.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+        let bottom = ys.iter().copied().fold(f64::INFINITY, f64::min);
+        let mut grid = vec![vec![' '; PLOT_WIDTH]; PLOT_HEIGHT];
+        for (column, &(_, y)) in samples.iter().enumerate() {
+            if let Some(y) = y {
+                // A constant result is drawn across the middle.
+                let height = if top > bottom { (y - bottom) / (top - bottom) } else { 0.5 };
+                let row = ((1.0 - height) * (PLOT_HEIGHT - 1) as f64).round() as usize;
+                grid[row][column] = '*';
+            }
+        }
+        let (top, bottom) = (self.display(Value::Float(top)), self.display(Value::Float(bottom)));
+        let margin = top.len().max(bottom.len());
+        let mut lines = Vec::new();
+        for (row, cells) in grid.iter().enumerate() {
+            let label = match row {
+                0 => top.as_str(),
+                _ if row == PLOT_HEIGHT - 1 => bottom.as_str(),
+                _ => "",
+            };
+            let cells: String = cells.iter().collect();
+            lines.push(format!("{:>margin$} |{}", label, cells.trim_end(), margin = margin));
+        }
+        lines.push(format!("{:>margin$} +{}", "", "-".repeat(PLOT_WIDTH), margin = margin));
+        let (lo, hi) = (self.display(Value::Float(lo)), self.display(Value::Float(hi)));
+        lines.push(format!("{:>margin$}  {:<width$}{}", "", lo, hi, margin = margin,
+                           width = PLOT_WIDTH.saturating_sub(hi.len())));
+        lines.join("\n")
+    }
+
+    /// Evaluate `input` `count` times by walking the tree and `count` times
+    /// with the compiled program, reporting both times and whether the
+    /// results agree. The program is given the values of the session
+    /// variables, which must be integers.
+    fn bench(&self, count: u32, input: &str) -> String {
+        let language = self.settings.language;
+        let expr = match self.parse(input) {
+            Ok(expr) => expr,
+            Err(error) => return syntax_message_in(error, language),
+        };
+        let program = match compile(&expr) {
+            Ok(program) => program,
+            Err(error) => return eval_message_in(error, language),
+        };
+        let mut env = Vec::new();
+        for name in program.vars() {
+            match self.vars.get(name) {
+                Some(&Value::Int(n)) => env.push(n),
+                Some(_) => return eval_message_in(EvalError::NotCompilable, language),
+                None => return eval_message_in(EvalError::UnknownVariable(name.clone()), language),
+            }
+        }
+        let start = Instant::now();
+        let mut walked = evaluate(&expr, self);
+        for _ in 1..count {
+            walked = evaluate(&expr, self);
+        }
+        let walk_time = start.elapsed();
+        let start = Instant::now();
+        let mut ran = run(&program, &env);
+        for _ in 1..count {
+            ran = run(&program, &env);
+        }
+        let run_time = start.elapsed();
+        self.warnings.borrow_mut().clear();
+        let walked = match walked {
+            Ok(value) => self.display(value),
+            Err(error) => eval_message_in(error, language),
+        };
+        let ran = match ran {
+            Ok(n) => self.display(Value::Int(n)),
+            Err(error) => eval_message_in(error, language),
+        };
+        let verdict = if walked == ran { "Same result." } else { "The results differ!" };
+        format!("evaluate: {} in {:?} ({:?} each)\nrun: {} in {:?} ({:?} each)\n{}",
+                walked, walk_time, walk_time / count, ran, run_time, run_time / count, verdict)
+    }
+
+    /// Write the transcript to `path`, one line of input per line, so that
+    /// `--file` can replay the session, recreating its variables, functions
+    /// and settings.
+    fn export(&self, path: &str) -> String {
+        let mut script = self.transcript.join("\n");
+        script.push('\n');
+        match fs::write(path, script) {
+            Ok(()) => format!("Exported {} lines to {}.", self.transcript.len(), path),
+            Err(error) => format!("Could not write {}: {}", path, error),
+        }
+    }
+
+    /// Run a REPL command, i.e. a line starting with `:`, and return the
+    /// text to print.
+    pub fn command(&mut self, line: &str) -> String {
+        let mut words = line.split_whitespace();
+        let name = words.next();
+        let rest = line[name.map_or(0, str::len)..].trim();
+        match name {
+            Some(":mode") => self.settings.describe(),
+            Some(":reset") => {
+                self.settings = self.startup.clone();
+                String::from("Settings restored to those the session started with.")
+            },
+            Some(":sexpr") => match self.parse(rest) {
+                Ok(expr) => to_sexpr(&expr),
+                Err(error) => syntax_message_in(error, self.settings.language),
+            },
+            Some(":help") => match operator_help(rest) {
+                Some(help) => help,
+                None if rest.is_empty() => String::from("Usage: :help OPERATOR"),
+                None => format!("There is no operator {}.", rest),
+            },
+            Some(":draw") => match self.parse(rest) {
+                Ok(expr) => to_tree(&expr),
+                Err(error) => syntax_message_in(error, self.settings.language),
+            },
+            Some(":words") => match self.parse(rest).map(|expr| evaluate(&expr, self)) {
+                Ok(Ok(value)) => match value.truncated() {
+                    Value::Int(n) => to_words(i64::from(n)),
+                    Value::Typed(n, _) => to_words(n),
+                    Value::Float(x) if x.fract() == 0.0 && x.abs() < 9.2e18 => to_words(x as i64),
+                    _ => format!("Only integers can be spelled out, not {}.", self.display(value)),
+                },
+                Ok(Err(error)) => eval_message_in(error, self.settings.language),
+                Err(error) => syntax_message_in(error, self.settings.language),
+            },
+            Some(":trace") => match self.parse(rest) {
+                Ok(expr) => match trace(expr, self) {
+                    Ok(steps) => steps.join("\n"),
+                    Err(error) => eval_message_in(error, self.settings.language),
+                },
+                Err(error) => syntax_message_in(error, self.settings.language),
+            },
+            Some(":precedence") => match self.parse(rest) {
+                Ok(expr) => {
+                    let mut notes = precedence_notes(&lex_with(rest, self.settings.decimal_sep));
+                    notes.push(format!("Grouping: {}", to_grouped(&expr)));
+                    notes.join("\n")
+                },
+                Err(error) => syntax_message_in(error, self.settings.language),
+            },
+            Some(":cmp") => match split_statements(rest) {
+                Some((left, right)) => self.compare_inputs(left, right),
+                None => String::from("Usage: :cmp EXPR ; EXPR"),
+            },
+            Some(":check") => match self.parse_statement_located(rest) {
+                Ok(_) => String::from("OK"),
+                Err((error, index)) => {
+                    let token = lex_with(rest, self.settings.decimal_sep).into_iter().nth(index);
+                    format!("{}\n{}", syntax_message_in(error, self.settings.language),
+                            position_message_in(index, token, self.settings.language))
+                },
+            },
+            Some(":search") => {
+                let found: Vec<String> = self.search(rest).into_iter()
+                    .map(|index| format!("#{}: {} = {}", index, self.history[index - 1],
+                                         self.display(self.results[index - 1])))
+                    .collect();
+                if found.is_empty() {
+                    String::from("No matching inputs.")
+                } else {
+                    found.join("\n")
+                }
+            },
+            Some(":define") => self.define(rest),
+            Some(":export") if !rest.is_empty() => self.export(rest),
+            Some(":export") => String::from("Usage: :export FILE"),
+            Some(":undo") => self.undo(),
+            Some(":repeat") => match parse_repeat(rest) {
+                Some((count, input)) => self.repeat(count, input),
+                None => String::from("Usage: :repeat N EXPR"),
+            },
+            Some(":plot") => {
+                let mut words = rest.rsplitn(4, char::is_whitespace);
+                let (hi, lo, var) = (words.next(), words.next(), words.next());
+                let input = words.next().map(str::trim).filter(|input| !input.is_empty());
+                match (input, var, lo.map(str::parse), hi.map(str::parse)) {
+                    (Some(input), Some(var), Some(Ok(lo)), Some(Ok(hi))) if lo < hi =>
+                        self.plot(input, var, lo, hi),
+                    _ => String::from("Usage: :plot EXPR VAR LO HI, with LO below HI"),
+                }
+            },
+            Some(":bench") => match rest.rsplit_once(char::is_whitespace) {
+                Some((input, count)) => match count.parse() {
+                    Ok(count) if count > 0 => self.bench(count, input.trim()),
+                    _ => String::from("Usage: :bench EXPR N"),
+                },
+                None => String::from("Usage: :bench EXPR N"),
+            },
+            Some(":numbers") => match words.next() {
+                Some("integer") => {
+                    self.settings.numbers = Numbers::Integer;
+                    String::from("Numbers set to integer.")
+                },
+                Some("float") => {
+                    self.settings.numbers = Numbers::Float;
+                    String::from("Numbers set to float.")
+                },
+                Some("mixed") => {
+                    self.settings.numbers = Numbers::Mixed;
+                    String::from("Numbers set to mixed integer and float.")
+                },
+                _ => String::from("Usage: :numbers integer|float|mixed"),
+            },
+            Some(":nan") => match words.next() {
+                Some("error") => {
+                    self.settings.nan = NanMode::Error;
+                    String::from("Non-finite results are errors.")
+                },
+                Some("allow") => {
+                    self.settings.nan = NanMode::Allow;
+                    String::from("Non-finite results are allowed.")
+                },
+                _ => String::from("Usage: :nan error|allow"),
+            },
+            Some(":syntax") => match words.next() {
+                Some("infix") => {
+                    self.settings.syntax = Syntax::Infix;
+                    String::from("Syntax set to infix.")
+                },
+                Some("rpn") => {
+                    self.settings.syntax = Syntax::Rpn;
+                    String::from("Syntax set to rpn.")
+                },
+                _ => String::from("Usage: :syntax infix|rpn"),
+            },
+            Some(":recursion-limit") => match words.next().map(str::parse) {
+                Some(Ok(limit)) if limit <= MAX_RECURSION_LIMIT => {
+                    self.settings.recursion_limit = limit;
+                    format!("Recursion limit set to {}.", limit)
+                },
+                _ => format!("Usage: :recursion-limit N, with N from 0 to {}",
+                             MAX_RECURSION_LIMIT),
+            },
+            Some(":raw") => match words.next() {
+                Some("on") => {
+                    self.settings.raw = true;
+                    String::from("Raw output on.")
+                },
+                Some("off") => {
+                    self.settings.raw = false;
+                    String::from("Raw output off.")
+                },
+                _ => String::from("Usage: :raw on|off"),
+            },
+            Some(":lenient-vars") => match words.next() {
+                Some("on") => {
+                    self.settings.lenient_vars = true;
+                    String::from("Undefined variables are read as 0.")
+                },
+                Some("off") => {
+                    self.settings.lenient_vars = false;
+                    String::from("Undefined variables are errors.")
+                },
+                _ => String::from("Usage: :lenient-vars on|off"),
+            },
+            Some(":precision") => match words.next() {
+                Some("auto") => {
+                    self.settings.precision = None;
+                    self.precision_reply("Floats shown with as many decimal places as they need.")
+                },
+                Some(word) => match word.parse() {
+                    Ok(precision) if precision <= MAX_PRECISION => {
+                        self.settings.precision = Some(precision);
+                        self.precision_reply(&format!("Floats shown with {} decimal places.",
+                                                      precision))
+                    },
+                    _ => format!("Usage: :precision N|auto, with N from 0 to {}", MAX_PRECISION),
+                },
+                None => format!("Usage: :precision N|auto, with N from 0 to {}", MAX_PRECISION),
+            },
+            Some(":modulus") => match words.next() {
+                Some("off") => {
+                    self.settings.modulus = None;
+                    String::from("Modular arithmetic off.")
+                },
+                Some(word) => match word.parse() {
+                    Ok(modulus) if modulus >= 2 => {
+                        self.settings.modulus = Some(modulus);
+                        self.settings.ring = None;
+                        format!("Integer results reduced modulo {}.", modulus)
+                    },
+                    _ => String::from("Usage: :modulus N|off, with N from 2 to 2147483647"),
+                },
+                None => String::from("Usage: :modulus N|off, with N from 2 to 2147483647"),
+            },
+            Some(":ring") => match words.next() {
+                Some("off") => {
+                    self.settings.ring = None;
+                    String::from("Ring arithmetic off.")
+                },
+                Some(word) => match word.parse() {
+                    Ok(width) if (1..=30).contains(&width) => {
+                        self.settings.ring = Some(width);
+                        self.settings.modulus = None;
+                        format!("Integer results wrapped to {} bits.", width)
+                    },
+                    _ => String::from("Usage: :ring N|off, with N from 1 to 30"),
+                },
+                None => String::from("Usage: :ring N|off, with N from 1 to 30"),
+            },
+            Some(":decimal-sep") => match words.next() {
+                Some(".") => {
+                    self.settings.decimal_sep = '.';
+                    String::from("Decimal separator set to '.', arguments separated by ','.")
+                },
+                Some(",") => {
+                    self.settings.decimal_sep = ',';
+                    String::from("Decimal separator set to ',', arguments separated by ';'.")
+                },
+                _ => String::from("Usage: :decimal-sep .|,"),
+            },
+            Some(":operators") => match words.next() {
+                Some("all") => {
+                    self.settings.operators = String::from(OPERATORS);
+                    String::from("All operators enabled.")
+                },
+                Some(operators) if operators.chars().all(|c| OPERATORS.contains(c)) => {
+                    self.settings.operators = OPERATORS.chars()
+                        .filter(|&c| operators.contains(c))
+                        .collect();
+                    format!("Operators enabled: {}", self.settings.operators)
+                },
+                _ => format!("Usage: :operators all|SYMBOLS, with SYMBOLS from {}", OPERATORS),
+            },
+            Some(":format") => match words.next() {
+                Some("fixed") => {
+                    self.settings.format = Format::Fixed;
+                    String::from("Floats shown in decimal notation.")
+                },
+                Some("sci") => {
+                    self.settings.format = Format::Sci;
+                    String::from("Floats shown in scientific notation.")
+                },
+                Some("auto") => {
+                    self.settings.format = Format::Auto;
+                    String::from("Very large and small floats shown in scientific notation.")
+                },
+                _ => String::from("Usage: :format fixed|sci|auto"),
+            },
+            Some(":lang") => match words.next().and_then(Language::from_code) {
+                Some(Language::English) => {
+                    self.settings.language = Language::English;
+                    String::from("Messages in English.")
+                },
+                Some(Language::Spanish) => {
+                    self.settings.language = Language::Spanish;
+                    String::from("Mensajes en español.")
+                },
+                None => String::from("Usage: :lang en|es"),
+            },
+            Some(":quotient") => match words.next() {
+                Some("truncate") => {
+                    self.settings.quotient = Quotient::Truncate;
+                    String::from("Integer division shows the quotient.")
+                },
+                Some("remainder") => {
+                    self.settings.quotient = Quotient::Remainder;
+                    String::from("Integer division shows the quotient and remainder.")
+                },
+                Some("mixed") => {
+                    self.settings.quotient = Quotient::Mixed;
+                    String::from("Integer division shows a mixed number.")
+                },
+                Some("divmod") => {
+                    self.settings.quotient = Quotient::DivMod;
+                    String::from("Integer division and % show the quotient and remainder.")
+                },
+                _ => String::from("Usage: :quotient truncate|remainder|mixed|divmod"),
+            },
+            Some(":divmod") => match words.next() {
+                Some("on") => {
+                    self.settings.quotient = Quotient::DivMod;
+                    String::from("Integer division and % show the quotient and remainder.")
+                },
+                Some("off") => {
+                    self.settings.quotient = Quotient::Truncate;
+                    String::from("Integer division shows the quotient.")
+                },
+                _ => String::from("Usage: :divmod on|off"),
+            },
+            Some(":bits") => match words.next() {
+                Some("off") => {
+                    self.settings.bits = Bits::Off;
+                    String::from("Integers shown in decimal.")
+                },
+                Some("bin") => {
+                    self.settings.bits = Bits::Bin;
+                    String::from("Integers shown as binary bit patterns.")
+                },
+                Some("hex") => {
+                    self.settings.bits = Bits::Hex;
+                    String::from("Integers shown as hexadecimal bit patterns.")
+                },
+                _ => String::from("Usage: :bits off|bin|hex"),
+            },
+            Some(name) => format!("Unknown command: {}", name),
+            None => String::from("Unknown command."),
+        }
+    }
+}
+
+
+/// Either kind of error from running an expression through the pipeline.
+pub enum CalcError {
+    Syntax(SyntaxError),
+    Eval(EvalError),
+}
+
+impl From<SyntaxError> for CalcError {
+    fn from(error: SyntaxError) -> CalcError {
+        CalcError::Syntax(error)
+    }
+}
+
+impl From<EvalError> for CalcError {
+    fn from(error: EvalError) -> CalcError {
+        CalcError::Eval(error)
+    }
+}
+
+
+/// Lazily parse and evaluate each input in turn, in a session with the
+/// default settings. As in the REPL, each successful result is recorded,
+/// so later inputs may refer to it as `#N`.
+pub fn eval_many<'a>(inputs: impl Iterator<Item=&'a str> + 'a)
+                     -> impl Iterator<Item=Result<Value, CalcError>> + 'a {
+    let mut calculator = Calculator::new();
+    inputs.map(move |input| {
+        let value = evaluate(&calculator.parse(input)?, &calculator);
+        calculator.warnings.borrow_mut().clear();
+        let value = value?;
+        calculator.record(input, value);
+        Ok(value)
+    })
+}
+
+
+/// Split `input` at its first `;` outside parentheses into two non-empty
+/// inputs. The `;` between the arguments of a function under
+/// `:decimal-sep ,` is inside the call's parentheses, so it is skipped.
+fn split_statements(input: &str) -> Option<(&str, &str)> {
+    let mut depth = 0;
+    for (position, c) in input.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ';' if depth == 0 => {
+                let (left, right) = (input[..position].trim(), input[position + 1..].trim());
+                return Some((left, right)).filter(|_| !left.is_empty() && !right.is_empty());
+            },
+            _ => {},
+        }
+    }
+    None
+}
+
+
+/// Split the arguments of `:repeat N EXPR` into the count, which must be
+/// at least 1, and the expression.
+pub fn parse_repeat(args: &str) -> Option<(u32, &str)> {
+    let mut parts = args.splitn(2, char::is_whitespace);
+    let count = parts.next()?.parse().ok().filter(|&count| count > 0)?;
+    let input = parts.next()?.trim();
+    if input.is_empty() {
+        return None;
+    }
+    Some((count, input))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recursion_limit_is_capped() {
+        let mut calculator = Calculator::new();
+        assert_eq!(calculator.command(":recursion-limit 500"), "Recursion limit set to 500.");
+        assert_eq!(calculator.command(":recursion-limit 501"),
+                   "Usage: :recursion-limit N, with N from 0 to 500");
+        assert_eq!(calculator.settings.recursion_limit, 500);
+    }
+
+    #[test]
+    fn reset_restores_the_startup_settings() {
+        let mut calculator = Calculator::new();
+        calculator.startup.numbers = Numbers::Float;
+        calculator.startup.language = Language::Spanish;
+        calculator.settings = calculator.startup.clone();
+        calculator.command(":numbers integer");
+        calculator.command(":lang en");
+        calculator.command(":reset");
+        assert!(calculator.settings.numbers == Numbers::Float);
+        assert!(calculator.settings.language == Language::Spanish);
+    }
+
+    #[test]
+    fn check_gives_a_verdict_without_evaluating() {
+        let mut calculator = Calculator::new();
+        assert_eq!(calculator.command(":check 1 / 0"), "OK");
+        assert_eq!(calculator.command(":check 1 + 2 )"), "Mismatched ( and ).\nAt token 4: )");
+        assert_eq!(calculator.command(":check x = ( 1"),
+                   "Mismatched ( and ).\nAt the end of the input.");
+        assert!(calculator.results.is_empty());
+    }
+
+    #[test]
+    fn mode_shows_changed_settings() {
+        let mut calculator = Calculator::new();
+        calculator.command(":numbers float");
+        calculator.command(":syntax rpn");
+        let mode = calculator.command(":mode");
+        assert!(mode.lines().any(|line| line == "numbers: float"));
+        assert!(mode.lines().any(|line| line == "syntax: rpn"));
+        assert!(mode.lines().any(|line| line == "nan: error"));
+        assert!(mode.lines().any(|line| line == "raw: off"));
+        assert_eq!(calculator.command(":nonsense"), "Unknown command: :nonsense");
+    }
+
+    #[test]
+    fn repeat_counts_are_parsed() {
+        assert_eq!(parse_repeat("3 1 + 2"), Some((3, "1 + 2")));
+        assert_eq!(parse_repeat("10   2 ^ 20  "), Some((10, "2 ^ 20")));
+        assert_eq!(parse_repeat("0 1 + 2"), None);
+        assert_eq!(parse_repeat("-1 1 + 2"), None);
+        assert_eq!(parse_repeat("x 1 + 2"), None);
+        assert_eq!(parse_repeat("3"), None);
+        assert_eq!(parse_repeat("3   "), None);
+    }
+
+    #[test]
+    fn raw_mode_can_be_switched() {
+        let mut calculator = Calculator::new();
+        assert_eq!(calculator.command(":raw on"), "Raw output on.");
+        assert!(calculator.settings.raw);
+        assert!(calculator.command(":mode").lines().any(|line| line == "raw: on"));
+        assert_eq!(calculator.command(":raw off"), "Raw output off.");
+        assert!(!calculator.settings.raw);
+    }
+
+    #[test]
+    fn raw_mode_displays_the_bare_number() {
+        let mut calculator = Calculator::new();
+        calculator.command(":precision 2");
+        assert_eq!(calculator.display(Value::Float(1.0 / 3.0)), "0.33~");
+        calculator.command(":raw on");
+        assert_eq!(calculator.display(Value::Float(1.0 / 3.0)), "0.33");
+        assert_eq!(calculator.display(Value::Int(-1234567)), "-1234567");
+    }
+
+    #[test]
+    fn eval_many_streams_each_result() {
+        let inputs = ["1 + 2", "2 $ 3", "1 / 0", "#1 * 10"];
+        let results: Vec<Result<Value, CalcError>> = eval_many(inputs.iter().copied()).collect();
+        assert_eq!(results.len(), 4);
+        assert!(matches!(results[0], Ok(Value::Int(3))));
+        assert!(matches!(results[1], Err(CalcError::Syntax(SyntaxError::UnknownSymbol(_)))));
+        assert!(matches!(results[2], Err(CalcError::Eval(EvalError::DivisionByZero))));
+        assert!(matches!(results[3], Ok(Value::Int(30))));
+    }
+
+    #[test]
+    fn rounded_floats_are_marked() {
+        let mut calculator = Calculator::new();
+        calculator.command(":precision 2");
+        assert_eq!(calculator.display(Value::Float(0.25)), "0.25");
+        assert_eq!(calculator.display(Value::Float(2.0)), "2.00");
+        assert_eq!(calculator.display(Value::Float(0.125)), "0.12~");
+        assert_eq!(calculator.display(Value::Float(2.0001)), "2.00~");
+    }
+
+    #[test]
+    fn auto_precision_shows_the_exact_value() {
+        let mut calculator = Calculator::new();
+        let sum = Value::Float(0.1 + 0.2);
+        calculator.command(":precision auto");
+        assert_eq!(calculator.display(sum), "0.30000000000000004");
+        assert_eq!(calculator.display(Value::Float(0.5)), "0.5");
+        calculator.command(":precision 3");
+        assert_eq!(calculator.display(sum), "0.300~");
+    }
+
+    #[test]
+    fn reset_restores_every_changed_setting() {
+        let mut calculator = Calculator::new();
+        let defaults = calculator.settings.describe();
+        for command in [":numbers float", ":syntax rpn", ":precision 3", ":raw on", ":ring 8",
+                        ":operators +-"] {
+            calculator.command(command);
+        }
+        assert!(calculator.settings.describe() != defaults);
+        calculator.command(":reset");
+        assert_eq!(calculator.settings.describe(), defaults);
+    }
+
+    #[test]
+    fn search_finds_matching_inputs() {
+        let mut calculator = Calculator::new();
+        calculator.record("1 + 2", Value::Int(3));
+        calculator.record("2 * 5", Value::Int(10));
+        calculator.record("11 + 2", Value::Int(13));
+        assert_eq!(calculator.search("+ 2"), vec![1, 3]);
+        assert_eq!(calculator.search("*"), vec![2]);
+        assert!(calculator.search("^").is_empty());
+    }
+
+    #[test]
+    fn disabled_division_is_a_syntax_error() {
+        let mut calculator = Calculator::new();
+        calculator.command(":operators +-*^!");
+        assert!(matches!(calculator.parse("5 / 2"),
+                         Err(SyntaxError::OperatorDisabled(ref symbol)) if symbol == "/"));
+        assert!(matches!(calculator.parse("5 % 2"), Err(SyntaxError::OperatorDisabled(_))));
+        let sum = calculator.parse("5 + 2").ok().expect("the input should parse");
+        assert!(matches!(evaluate(&sum, &calculator), Ok(Value::Int(7))));
+    }
+
+    #[test]
+    fn chained_assignment_groups_to_the_right() {
+        let calculator = Calculator::new();
+        match calculator.parse_statement("a = b = 5").ok().expect("the input should parse") {
+            Statement::Assignment(names, expr) => {
+                assert_eq!(names, vec!["a", "b"]);
+                assert!(matches!(expr, Expr::Number(5)));
+            },
+            _ => panic!("a = b = 5 should be an assignment"),
+        }
+        assert!(matches!(calculator.parse_statement("a = 1 = 5"), Err(SyntaxError::GeneralError)));
+    }
+
+    #[test]
+    fn registered_functions_can_be_called() {
+        let mut calculator = Calculator::new();
+        calculator.register_fn("double", 1, |args| match args[0] {
+            Value::Int(n) => Ok(Value::Int(n * 2)),
+            value => Err(EvalError::NotANumber(value)),
+        });
+        let call = calculator.parse("double ( 21 )").ok().expect("the call should parse");
+        assert!(matches!(evaluate(&call, &calculator), Ok(Value::Int(42))));
+        let wrong = calculator.parse("double ( 1 , 2 )").ok().expect("the call should parse");
+        assert!(matches!(evaluate(&wrong, &calculator),
+                         Err(EvalError::ArgumentCount(ref name, 1)) if name == "double"));
+    }
+
+    #[test]
+    fn sci_format_shows_floats_in_scientific_notation() {
+        let mut calculator = Calculator::new();
+        calculator.command(":numbers float");
+        calculator.command(":format sci");
+        assert_eq!(calculator.display(Value::Float(602000000000.0)), "6.02e11");
+        assert_eq!(calculator.display(Value::Float(0.0001)), "1e-4");
+        calculator.command(":format auto");
+        assert_eq!(calculator.display(Value::Float(602000000000.0)), "602000000000");
+        assert_eq!(calculator.display(Value::Float(0.0001)), "0.0001");
+        assert_eq!(calculator.display(Value::Float(0.00001)), "1e-5");
+        assert_eq!(calculator.display(Value::Float(1e16)), "1e16");
+    }
+
+    #[test]
+    fn bench_checks_that_both_evaluators_agree() {
+        let mut calculator = Calculator::new();
+        let report = calculator.command(":bench 1 + 2 * 3 10");
+        assert!(report.starts_with("evaluate: 7 in "));
+        assert!(report.contains("\nrun: 7 in "));
+        assert!(report.ends_with("\nSame result."));
+        calculator.vars.insert(String::from("x"), Value::Int(4));
+        assert!(calculator.command(":bench x / 0 2").ends_with("\nSame result."));
+        // The compiled program knows nothing of the modulus.
+        calculator.command(":modulus 5");
+        assert!(calculator.command(":bench 2 + 4 3").ends_with("\nThe results differ!"));
+    }
+
+    #[test]
+    fn bits_show_twos_complement_patterns() {
+        assert_eq!(Bits::Hex.pattern(-1, IntType::I32).as_deref(), Some("0xffffffff"));
+        assert_eq!(Bits::Hex.pattern(-256, IntType::I32).as_deref(), Some("0xffffff00"));
+        assert_eq!(Bits::Bin.pattern(-256, IntType::I32),
+                   Some(format!("0b{}{}", "1".repeat(24), "0".repeat(8))));
+        assert_eq!(Bits::Hex.pattern(-1, IntType::I8).as_deref(), Some("0xff"));
+        assert!(Bits::Off.pattern(-1, IntType::I32).is_none());
+        let mut calculator = Calculator::new();
+        calculator.command(":bits bin");
+        assert_eq!(calculator.display(Value::Int(-1)), format!("0b{}", "1".repeat(32)));
+        assert_eq!(calculator.display(Value::Float(-1.5)), "-1.5");
+    }
+
+    #[test]
+    fn statements_split_at_the_first_top_level_semicolon() {
+        assert_eq!(split_statements("2*3 ; 6"), Some(("2*3", "6")));
+        assert_eq!(split_statements("max ( 1 ; 2 ) ; 2"), Some(("max ( 1 ; 2 )", "2")));
+        assert_eq!(split_statements("1 ; 2 ; 3"), Some(("1", "2 ; 3")));
+        assert!(split_statements("2*3 ;").is_none());
+        assert!(split_statements("2*3").is_none());
+    }
+
+    #[test]
+    fn cmp_reports_equality_or_the_difference() {
+        let mut calculator = Calculator::new();
+        assert_eq!(calculator.command(":cmp 2*3 ; 6"), "Equal: both are 6.");
+        assert_eq!(calculator.command(":cmp 2*3 ; 5"), "Not equal: 6 - 5 = 1.");
+        assert_eq!(calculator.command(":cmp 2*3"), "Usage: :cmp EXPR ; EXPR");
+    }
+
+    #[test]
+    fn precision_warns_in_integer_mode_but_is_kept() {
+        let mut calculator = Calculator::new();
+        assert_eq!(calculator.command(":precision 3"),
+                   "Floats shown with 3 decimal places.\n\
+                    Warning: precision has no effect in integer mode, except on quantities.");
+        assert_eq!(calculator.settings.precision, Some(3));
+        calculator.command(":numbers float");
+        assert_eq!(calculator.command(":precision 2"), "Floats shown with 2 decimal places.");
+        assert_eq!(calculator.display(Value::Float(1.0 / 3.0)), "0.33~");
+    }
+
+    #[test]
+    fn commands_reject_a_misplaced_assignment() {
+        let mut calculator = Calculator::new();
+        for command in [":sexpr 1 + 2 = 3", ":draw 1 + 2 = 3", ":trace 1 + 2 = 3",
+                        ":precedence 1 + 2 = 3", ":words 1 + 2 = 3", ":plot 1 + x = 1 x 0 3"] {
+            assert_eq!(calculator.command(command), "Syntax error.", "{}", command);
+        }
+    }
+
+    #[test]
+    fn precision_is_capped() {
+        let mut calculator = Calculator::new();
+        calculator.command(":numbers float");
+        assert_eq!(calculator.command(":precision 70000"),
+                   "Usage: :precision N|auto, with N from 0 to 17");
+        assert!(calculator.settings.precision.is_none());
+        assert_eq!(calculator.command(":precision 17"), "Floats shown with 17 decimal places.");
+        assert_eq!(calculator.display(Value::Float(1.0 / 3.0)), "0.33333333333333331");
+    }
+}
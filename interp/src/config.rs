@@ -0,0 +1,155 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use calculator::{Settings, MAX_RECURSION_LIMIT, OPERATORS};
+use value::MAX_PRECISION;
+
+/// Preferences read at startup from `interp.toml`. Every key is optional
+/// and those which are missing keep their defaults. The settings use the
+/// names listed by `:mode`, for example:
+///
+/// ```toml
+/// numbers = "float"
+/// precision = 4
+/// recursion-limit = 50
+/// prompt = "> "
+/// ```
+#[derive(Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// The prompt, unless `INTERP_PROMPT` is set.
+    pub prompt: Option<String>,
+    #[serde(flatten)]
+    pub settings: Settings,
+}
+
+/// Why a configuration file could not be used.
+pub enum ConfigError {
+    Io(PathBuf, io::Error),
+    Toml(PathBuf, toml::de::Error),
+    /// A setting with a value which its command would not accept.
+    Invalid(PathBuf, &'static str),
+}
+
+impl Config {
+    /// Read the configuration from `path`. A missing file is not an error,
+    /// and gives the defaults.
+    pub fn load(path: &Path) -> Result<Config, ConfigError> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(ref error) if error.kind() == io::ErrorKind::NotFound =>
+                return Ok(Config::default()),
+            Err(error) => return Err(ConfigError::Io(path.to_path_buf(), error)),
+        };
+        let mut config: Config = toml::from_str(&text)
+            .map_err(|error| ConfigError::Toml(path.to_path_buf(), error))?;
+        let settings = &mut config.settings;
+        if settings.decimal_sep != '.' && settings.decimal_sep != ',' {
+            return Err(ConfigError::Invalid(path.to_path_buf(), "decimal-sep"));
+        }
+        if settings.recursion_limit > MAX_RECURSION_LIMIT {
+            return Err(ConfigError::Invalid(path.to_path_buf(), "recursion-limit"));
+        }
+        if settings.precision.is_some_and(|precision| precision > MAX_PRECISION) {
+            return Err(ConfigError::Invalid(path.to_path_buf(), "precision"));
+        }
+        if settings.modulus.is_some_and(|modulus| modulus < 2) {
+            return Err(ConfigError::Invalid(path.to_path_buf(), "modulus"));
+        }
+        if settings.ring.is_some_and(|width| !(1..=30).contains(&width)) ||
+           (settings.ring.is_some() && settings.modulus.is_some()) {
+            return Err(ConfigError::Invalid(path.to_path_buf(), "ring"));
+        }
+        if !settings.operators.chars().all(|c| OPERATORS.contains(c)) {
+            return Err(ConfigError::Invalid(path.to_path_buf(), "operators"));
+        }
+        settings.operators = OPERATORS.chars()
+            .filter(|&c| settings.operators.contains(c))
+            .collect();
+        Ok(config)
+    }
+}
+
+
+/// Where the configuration file is kept: `interp.toml` in
+/// `$XDG_CONFIG_HOME`, or else in `~/.config`.
+pub fn config_path() -> Option<PathBuf> {
+    let dir = match env::var_os("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(dir.join("interp.toml"))
+}
+
+
+/// The message printed when the configuration file cannot be used.
+pub fn config_message(error: ConfigError) -> String {
+    match error {
+        ConfigError::Io(path, error) =>
+            format!("Could not read {}: {}", path.display(), error),
+        ConfigError::Toml(path, error) =>
+            format!("Could not parse {}: {}", path.display(), error.to_string().trim_end()),
+        ConfigError::Invalid(path, name) =>
+            format!("Invalid value for {} in {}.", name, path.display()),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process;
+
+    use calculator::Calculator;
+    use eval::evaluate;
+
+    /// Load a configuration file holding `text`.
+    fn load(name: &str, text: &str) -> Result<Config, ConfigError> {
+        let path = env::temp_dir().join(format!("interp-test-{}-{}.toml", name, process::id()));
+        fs::write(&path, text).expect("the configuration should be written");
+        let config = Config::load(&path);
+        fs::remove_file(&path).ok();
+        config
+    }
+
+    #[test]
+    fn recursion_limit_beyond_the_maximum_is_invalid() {
+        assert!(matches!(load("limit-ok", "recursion-limit = 500"),
+                         Ok(ref config) if config.settings.recursion_limit == 500));
+        assert!(matches!(load("limit-high", "recursion-limit = 501"),
+                         Err(ConfigError::Invalid(_, "recursion-limit"))));
+    }
+
+    #[test]
+    fn a_sample_config_seeds_the_calculator() {
+        let text = "numbers = \"float\"\nprecision = 2\nprompt = \"> \"\n";
+        let config = load("sample", text).ok().expect("the sample should load");
+        assert_eq!(config.prompt, Some(String::from("> ")));
+        let mut calculator = Calculator::new();
+        calculator.settings = config.settings;
+        let expr = calculator.parse("1 / 3").ok().expect("the input should parse");
+        let value = evaluate(&expr, &calculator).ok().expect("the input should evaluate");
+        assert_eq!(calculator.display(value), "0.33~");
+        assert_eq!(calculator.settings.recursion_limit, Settings::default().recursion_limit);
+    }
+
+    #[test]
+    fn a_missing_file_gives_the_defaults() {
+        let path = env::temp_dir().join(format!("interp-test-missing-{}.toml", process::id()));
+        let config = Config::load(&path).ok().expect("a missing file should not be an error");
+        assert!(config.prompt.is_none());
+        assert_eq!(config.settings.describe(), Settings::default().describe());
+    }
+
+    #[test]
+    fn precision_beyond_the_maximum_is_invalid() {
+        assert!(matches!(load("precision-ok", "precision = 17"),
+                         Ok(ref config) if config.settings.precision == Some(17)));
+        assert!(matches!(load("precision-high", "precision = 100000"),
+                         Err(ConfigError::Invalid(_, "precision"))));
+    }
+}
@@ -0,0 +1,1386 @@
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::iter;
+use std::time::{Duration, Instant};
+
+use calculator::{Calculator, NanMode, Numbers, Quotient, Settings};
+use parser::{to_sexpr, Comparison, Expr};
+use value::{IntType, Units, Value};
+
+#[derive(Clone)]
+pub enum EvalError {
+    DivisionByZero,
+    NoSuchResult(usize, usize),
+    NotFinite(f64),
+    FloatInIntegerMode(f64),
+    UnknownFunction(String),
+    NoArguments(String),
+    ArgumentCount(String, usize),
+    EmptyRange(Value, Value),
+    UnknownVariable(String),
+    RecursionLimitExceeded(usize),
+    Overflow(IntType),
+    MismatchedWidths(IntType, IntType),
+    /// `!` of something other than a non-negative integer.
+    FactorialDomain(Value),
+    /// `n !` overflowed, and `(n, largest, width)` records the largest
+    /// operand whose factorial fits.
+    FactorialOverflow(i64, i64, IntType),
+    /// A boolean where a number is needed, as in `( 1 < 2 ) + 3`.
+    NotANumber(Value),
+    /// `compile` met something other than integer arithmetic.
+    NotCompilable,
+    /// An integer raised to a negative power, which is not an integer.
+    NegativeExponent,
+    /// Evaluation took longer than the given time limit.
+    Timeout(Duration),
+    /// An assignment to a constant introduced with `:define`.
+    ConstantAssignment(String),
+    /// `ans` before there are any results.
+    NoPreviousResult,
+    /// Under `:modulus`, `(divisor, modulus)` for a division by a number
+    /// which has no inverse.
+    NoInverse(i32, i32),
+    /// Quantities whose units do not agree, as in `1 m + 1 s`.
+    IncompatibleUnits(Units, Units),
+    /// A quantity raised to a power other than an integer, or a power with
+    /// units.
+    UnitExponent,
+}
+
+/// Something questionable noticed during evaluation, or in a command,
+/// which, unlike an `EvalError`, does not stop it.
+pub enum Warning {
+    /// An undefined variable was read as 0 under `:lenient-vars on`.
+    UnknownVariable(String),
+    /// A session variable was assigned with the name of a constant.
+    ShadowsConstant(String),
+    /// `:precision` was set in integer mode, where only quantities are
+    /// floats.
+    PrecisionInIntegerMode,
+}
+
+
+/// Apply a binary operator. Integer operands use `int_op`, which returns
+/// `None` on overflow; if either operand is a float, both are promoted and
+/// `float_op` is used instead. Non-finite float results are errors unless
+/// `:nan allow` is set.
+///
+/// Integers without a type suffix are 32 bits wide. When combined with an
+/// integer of declared width, they take on that width, so `5i8 + 3` is
+/// `8i8`. Combining two different declared widths is an error.
+///
+/// A quantity is a float with a unit, and a plain number is dimensionless.
+/// `units` gives the unit of the result, such as `Units::times` for `*`,
+/// or `None` if the operands' units cannot be combined, as for `1 m + 1 s`.
+fn arithmetic(l_value: Value,
+              r_value: Value,
+              int_op: fn(i64, i64) -> Option<i64>,
+              float_op: fn(f64, f64) -> f64,
+              units: fn(Units, Units) -> Option<Units>,
+              settings: &Settings) -> Result<Value, EvalError> {
+    use EvalError::*;
+    let (l, r, width) = match (l_value, r_value) {
+        (Value::Bool(_), _) => return Err(NotANumber(l_value)),
+        (_, Value::Bool(_)) => return Err(NotANumber(r_value)),
+        (Value::Int(l), Value::Int(r)) => (i64::from(l), i64::from(r), None),
+        (Value::Typed(l, width), Value::Int(r)) => (l, i64::from(r), Some(width)),
+        (Value::Int(l), Value::Typed(r, width)) => (i64::from(l), r, Some(width)),
+        (Value::Typed(l, l_width), Value::Typed(r, r_width)) => {
+            if l_width != r_width {
+                return Err(MismatchedWidths(l_width, r_width));
+            }
+            (l, r, Some(l_width))
+        },
+        _ => {
+            let (l_units, r_units) = (l_value.units(), r_value.units());
+            let units = units(l_units, r_units).ok_or(IncompatibleUnits(l_units, r_units))?;
+            let result = float_op(l_value.to_f64(), r_value.to_f64());
+            if !result.is_finite() && settings.nan == NanMode::Error {
+                return Err(NotFinite(result));
+            }
+            return Ok(Value::quantity(result, units));
+        },
+    };
+    match (width, settings.reduction()) {
+        // The operands are already reduced, so the result fits in an i64.
+        (None, Some(modulus)) => int_op(l, r).map(|n| Value::Int(reduce(n, modulus)))
+                                             .ok_or(Overflow(IntType::I32)),
+        (None, None) => int_op(l, r).and_then(|n| i32::try_from(n).ok())
+                                    .map(Value::Int)
+                                    .ok_or(Overflow(IntType::I32)),
+        (Some(width), _) => {
+            if !width.contains(l) || !width.contains(r) {
+                return Err(Overflow(width));
+            }
+            int_op(l, r).filter(|&n| width.contains(n))
+                        .map(|n| Value::Typed(n, width))
+                        .ok_or(Overflow(width))
+        },
+    }
+}
+
+
+/// Apply `/` or `%`, which are errors for an integer divisor of zero.
+fn division(l_value: Value,
+            r_value: Value,
+            int_op: fn(i64, i64) -> Option<i64>,
+            float_op: fn(f64, f64) -> f64,
+            units: fn(Units, Units) -> Option<Units>,
+            settings: &Settings) -> Result<Value, EvalError> {
+    let integral = |value: Value| matches!(value, Value::Int(_) | Value::Typed(..));
+    if integral(l_value) && integral(r_value) && r_value.to_f64() == 0.0 {
+        return Err(EvalError::DivisionByZero);
+    }
+    arithmetic(l_value, r_value, int_op, float_op, units, settings)
+}
+
+
+/// Reduce `n` modulo `modulus`, giving a result from 0 to `modulus - 1`.
+fn reduce(n: i64, modulus: i32) -> i32 {
+    n.rem_euclid(i64::from(modulus)) as i32
+}
+
+
+/// Raise `base` to the non-negative power `exponent` modulo `modulus`, by
+/// repeated squaring.
+fn modular_pow(base: i32, exponent: i32, modulus: i32) -> i32 {
+    let modulus = i64::from(modulus);
+    let (mut base, mut exponent, mut result) = (i64::from(base).rem_euclid(modulus), exponent, 1);
+    while exponent > 0 {
+        if exponent % 2 == 1 {
+            result = result * base % modulus;
+        }
+        base = base * base % modulus;
+        exponent /= 2;
+    }
+    result as i32
+}
+
+
+/// Divide `l` by `r` modulo `modulus`, by multiplying by the inverse of `r`,
+/// which exists only if `r` and `modulus` have no common factor.
+fn modular_divide(l: i32, r: i32, modulus: i32) -> Result<Value, EvalError> {
+    if r == 0 {
+        return Err(EvalError::DivisionByZero);
+    }
+    // The extended Euclidean algorithm, keeping only the coefficient of r.
+    let (mut a, mut b) = (i64::from(modulus), i64::from(r));
+    let (mut x, mut y) = (0i64, 1i64);
+    while b != 0 {
+        let q = a / b;
+        (a, b) = (b, a - q * b);
+        (x, y) = (y, x - q * y);
+    }
+    if a != 1 {
+        return Err(EvalError::NoInverse(r, modulus));
+    }
+    Ok(Value::Int(reduce(i64::from(l) * i64::from(reduce(x, modulus)), modulus)))
+}
+
+
+/// Raise `l` to the power `r`, or return `None` on overflow.
+pub(crate) fn checked_pow(l: i64, r: i64) -> Option<i64> {
+    u32::try_from(r).ok().and_then(|r| l.checked_pow(r))
+}
+
+
+/// Apply `^`, also called as `pow ( l , r )`. A negative integer exponent
+/// is an error, since the result would not be an integer; in float mode
+/// it is fine.
+fn power(l_value: Value, r_value: Value, settings: &Settings) -> Result<Value, EvalError> {
+    let integral = |value: Value| matches!(value, Value::Int(_) | Value::Typed(..));
+    if integral(l_value) && integral(r_value) && r_value.to_f64() < 0.0 {
+        return Err(EvalError::NegativeExponent);
+    }
+    if let (Value::Int(l), Value::Int(r), Some(modulus)) =
+            (l_value, r_value, settings.reduction()) {
+        return Ok(Value::Int(modular_pow(l, r, modulus)));
+    }
+    // A quantity can be raised only to an integer power, without units.
+    if !l_value.units().is_dimensionless() || !r_value.units().is_dimensionless() {
+        // In float mode even a literal integer power is a float.
+        let units = match r_value {
+            Value::Int(n) => l_value.units().pow(n),
+            Value::Typed(n, _) => i32::try_from(n).ok().and_then(|n| l_value.units().pow(n)),
+            Value::Float(x) if x.fract() == 0.0 && x.abs() <= f64::from(i8::MAX) =>
+                l_value.units().pow(x as i32),
+            _ => None,
+        };
+        let units = units.ok_or(EvalError::UnitExponent)?;
+        let result = l_value.to_f64().powf(r_value.to_f64());
+        if !result.is_finite() && settings.nan == NanMode::Error {
+            return Err(EvalError::NotFinite(result));
+        }
+        return Ok(Value::quantity(result, units));
+    }
+    arithmetic(l_value, r_value, checked_pow, f64::powf, Units::same, settings)
+}
+
+
+/// Compute `sqrt ( value )`. The square root of an integer is exact when
+/// it is a perfect square. Otherwise it truncates in integer mode, as `/`
+/// does, and is a float in mixed mode. The root of a negative number is
+/// `nan`, so it is an error unless `:nan allow` is set in a mode with
+/// floats. A quantity needs an even power of each unit, so `sqrt ( 4 m )`
+/// is an error but the root of an area is a length.
+fn square_root(value: Value, settings: &Settings) -> Result<Value, EvalError> {
+    use EvalError::*;
+    let (n, width) = match value {
+        Value::Int(n) | Value::Quotient(n, ..) | Value::Remainder(n, ..) =>
+            (i64::from(n), None),
+        Value::Typed(n, width) => (n, Some(width)),
+        Value::Bool(_) => return Err(NotANumber(value)),
+        Value::Float(_) | Value::Quantity(..) => {
+            let mut powers = value.units().0;
+            for power in powers.iter_mut() {
+                if *power % 2 != 0 {
+                    return Err(UnitExponent);
+                }
+                *power /= 2;
+            }
+            let result = value.to_f64().sqrt();
+            if !result.is_finite() && settings.nan == NanMode::Error {
+                return Err(NotFinite(result));
+            }
+            return Ok(Value::quantity(result, Units(powers)));
+        },
+    };
+    if n < 0 {
+        return match settings.numbers {
+            Numbers::Mixed if settings.nan == NanMode::Allow => Ok(Value::Float(f64::NAN)),
+            _ => Err(NotFinite(f64::NAN)),
+        };
+    }
+    // The float root is within one of the integer root.
+    let mut root = (n as f64).sqrt() as i64;
+    while root * root > n {
+        root -= 1;
+    }
+    while (root + 1) * (root + 1) <= n {
+        root += 1;
+    }
+    if root * root != n && settings.numbers == Numbers::Mixed {
+        return Ok(Value::Float((n as f64).sqrt()));
+    }
+    match width {
+        Some(width) => Ok(Value::Typed(root, width)),
+        None => Ok(Value::Int(root as i32)),
+    }
+}
+
+
+/// Compute `value !` by repeated multiplication, checking for overflow at
+/// the operand's width. In float mode the operand must still be a whole
+/// number.
+pub(crate) fn factorial(value: Value, settings: &Settings) -> Result<Value, EvalError> {
+    use EvalError::*;
+    let (n, width) = match value {
+        Value::Int(n) | Value::Quotient(n, ..) | Value::Remainder(n, ..) =>
+            (i64::from(n), IntType::I32),
+        Value::Typed(n, width) => (n, width),
+        Value::Bool(_) => return Err(NotANumber(value)),
+        Value::Quantity(..) => return Err(FactorialDomain(value)),
+        Value::Float(x) => {
+            if x < 0.0 || x.fract() != 0.0 {
+                return Err(FactorialDomain(value));
+            }
+            let mut result: f64 = 1.0;
+            let mut k = 2.0;
+            while k <= x && result.is_finite() {
+                result *= k;
+                k += 1.0;
+            }
+            if !result.is_finite() && settings.nan == NanMode::Error {
+                return Err(NotFinite(result));
+            }
+            return Ok(Value::Float(result));
+        },
+    };
+    if n < 0 {
+        return Err(FactorialDomain(value));
+    }
+    let mut result: i64 = 1;
+    for k in 2..=n {
+        match result.checked_mul(k).filter(|&result| width.contains(result)) {
+            Some(product) => result = product,
+            None => return Err(FactorialOverflow(n, k - 1, width)),
+        }
+    }
+    match value {
+        Value::Typed(..) => Ok(Value::Typed(result, width)),
+        _ => Ok(Value::Int(result as i32)),
+    }
+}
+
+
+/// Whether `ordering` satisfies `comparison`. An ordering of `None`, i.e.
+/// a comparison involving `nan`, satisfies only `!=`.
+fn compare(comparison: Comparison, ordering: Option<Ordering>) -> bool {
+    use std::cmp::Ordering::*;
+    match (comparison, ordering) {
+        (Comparison::NotEqual, None) => true,
+        (_, None) => false,
+        (Comparison::Less, Some(ordering)) => ordering == Less,
+        (Comparison::LessEqual, Some(ordering)) => ordering != Greater,
+        (Comparison::Greater, Some(ordering)) => ordering == Greater,
+        (Comparison::GreaterEqual, Some(ordering)) => ordering != Less,
+        (Comparison::Equal, Some(ordering)) => ordering == Equal,
+        (Comparison::NotEqual, Some(ordering)) => ordering != Equal,
+    }
+}
+
+
+/// Call a function on its evaluated arguments. Functions defined during
+/// the session take precedence over those registered with
+/// `Calculator::register_fn`, and both over the built-in functions below.
+///
+/// `sum`, `product` and `avg` take one or more arguments. `avg` divides
+/// by the count as `/` would, so it truncates unless the total is a float.
+/// `min` and `max` also take one or more arguments.
+///
+/// `clamp ( value , lo , hi )` constrains `value` to the range `[lo, hi]`.
+///
+/// `pow ( x , y )` is `x ^ y`.
+///
+/// `default ( x , y )` is `x`, or `y` if `x` reads an undefined variable.
+/// Its arguments are evaluated lazily, so it is handled by
+/// `evaluate_unreduced` instead.
+///
+/// `abs ( x )` is the absolute value of `x`, and `sign ( x )` is -1, 0 or 1
+/// as `x` is negative, zero or positive.
+///
+/// `sqrt ( x )` is the square root of `x`; see `square_root`.
+///
+/// The names of built-in functions, like those of built-in constants, are
+/// not case-sensitive, so `ABS ( x )` and `Abs ( x )` are `abs ( x )`. The
+/// names of variables and of functions defined in the session or
+/// registered are case-sensitive, as they are chosen by the user.
+fn call_function(name: &str,
+                 args: &[Value],
+                 calculator: &Calculator,
+                 env: &Env) -> Result<Value, EvalError> {
+    use EvalError::*;
+    let settings = &*effective_settings(calculator, env);
+    if let Some(function) = calculator.functions.get(name) {
+        if args.len() != function.params.len() {
+            return Err(ArgumentCount(String::from(name), function.params.len()));
+        }
+        if env.depth >= settings.recursion_limit {
+            return Err(RecursionLimitExceeded(settings.recursion_limit));
+        }
+        let env = Env {
+            vars: function.params.iter().cloned().zip(args.iter().cloned()).collect(),
+            depth: env.depth + 1,
+            deadline: env.deadline,
+            ignore_modulus: env.ignore_modulus,
+        };
+        return evaluate_env(&function.body, calculator, &env);
+    }
+    if let Some(function) = calculator.native.get(name) {
+        if args.len() != function.arity {
+            return Err(ArgumentCount(String::from(name), function.arity));
+        }
+        return (function.call)(args);
+    }
+    let builtin = name.to_ascii_lowercase();
+    match builtin.as_str() {
+        "sum" | "product" | "avg" | "min" | "max" if args.is_empty() =>
+            Err(NoArguments(String::from(name))),
+        "sum" | "avg" => {
+            let mut total = args[0];
+            for &arg in &args[1..] {
+                total = arithmetic(total, arg, i64::checked_add, |l, r| l + r, Units::same,
+                                   settings)?;
+            }
+            if builtin == "sum" {
+                return Ok(total);
+            }
+            let count = if settings.numbers != Numbers::Float {
+                Value::Int(args.len() as i32)
+            } else {
+                Value::Float(args.len() as f64)
+            };
+            arithmetic(total, count, i64::checked_div, |l, r| l / r, Units::over, settings)
+        },
+        "product" => {
+            let mut total = args[0];
+            for &arg in &args[1..] {
+                total = arithmetic(total, arg, i64::checked_mul, |l, r| l * r, Units::times,
+                                   settings)?;
+            }
+            Ok(total)
+        },
+        "min" | "max" => {
+            if let Some(&arg) = args.iter().find(|arg| arg.is_bool()) {
+                return Err(NotANumber(arg));
+            }
+            if let Some(&arg) = args.iter().find(|arg| arg.units() != args[0].units()) {
+                return Err(IncompatibleUnits(args[0].units(), arg.units()));
+            }
+            let wanted = if builtin == "min" { Ordering::Less } else { Ordering::Greater };
+            let mut best = args[0];
+            for &arg in &args[1..] {
+                if arg.compare(best) == Some(wanted) {
+                    best = arg;
+                }
+            }
+            Ok(best)
+        },
+        "clamp" => {
+            if args.len() != 3 {
+                return Err(ArgumentCount(String::from(name), 3));
+            }
+            let (value, lo, hi) = (args[0], args[1], args[2]);
+            if let Some(&arg) = args.iter().find(|arg| arg.is_bool()) {
+                return Err(NotANumber(arg));
+            }
+            if let Some(&arg) = args.iter().find(|arg| arg.units() != args[0].units()) {
+                return Err(IncompatibleUnits(args[0].units(), arg.units()));
+            }
+            if lo.compare(hi) == Some(Ordering::Greater) {
+                return Err(EmptyRange(lo, hi));
+            }
+            if value.compare(lo) == Some(Ordering::Less) {
+                Ok(lo)
+            } else if value.compare(hi) == Some(Ordering::Greater) {
+                Ok(hi)
+            } else {
+                Ok(value)
+            }
+        },
+        "pow" => {
+            if args.len() != 2 {
+                return Err(ArgumentCount(String::from(name), 2));
+            }
+            power(args[0], args[1], settings)
+        },
+        "sqrt" => {
+            if args.len() != 1 {
+                return Err(ArgumentCount(String::from(name), 1));
+            }
+            square_root(args[0], settings)
+        },
+        "abs" | "sign" => {
+            if args.len() != 1 {
+                return Err(ArgumentCount(String::from(name), 1));
+            }
+            let abs = builtin == "abs";
+            match args[0] {
+                Value::Int(n) | Value::Quotient(n, ..) | Value::Remainder(n, ..) if abs =>
+                    n.checked_abs().map(Value::Int).ok_or(Overflow(IntType::I32)),
+                Value::Int(n) | Value::Quotient(n, ..) | Value::Remainder(n, ..) =>
+                    Ok(Value::Int(n.signum())),
+                Value::Typed(n, width) if abs => n.checked_abs().filter(|&n| width.contains(n))
+                                                              .map(|n| Value::Typed(n, width))
+                                                              .ok_or(Overflow(width)),
+                Value::Typed(n, width) => Ok(Value::Typed(n.signum(), width)),
+                Value::Float(x) if abs => Ok(Value::Float(x.abs())),
+                // Unlike f64::signum, the sign of zero is zero.
+                Value::Float(x) if x == 0.0 || x.is_nan() => Ok(Value::Float(x)),
+                Value::Float(x) => Ok(Value::Float(x.signum())),
+                Value::Quantity(x, units) if abs => Ok(Value::Quantity(x.abs(), units)),
+                Value::Quantity(x, _) if x == 0.0 || x.is_nan() => Ok(Value::Float(x)),
+                Value::Quantity(x, _) => Ok(Value::Float(x.signum())),
+                value @ Value::Bool(_) => Err(NotANumber(value)),
+            }
+        },
+        _ => Err(UnknownFunction(String::from(name))),
+    }
+}
+
+
+/// Whether a call of `name` is to the built-in `default`, which no
+/// function defined in the session or registered hides.
+fn is_default(name: &str, calculator: &Calculator) -> bool {
+    name.eq_ignore_ascii_case("default") && !calculator.functions.contains_key(name) &&
+        !calculator.native.contains_key(name)
+}
+
+
+/// The value of a built-in constant, which is used when no variable of
+/// the same name is in scope. The name is not case-sensitive, so `PI` is
+/// `pi`, but a variable `PI` still hides it.
+pub fn constant(name: &str) -> Option<f64> {
+    match name.to_ascii_lowercase().as_str() {
+        "e" => Some(std::f64::consts::E),
+        "pi" => Some(std::f64::consts::PI),
+        _ => None,
+    }
+}
+
+
+/// The environment an expression is evaluated in.
+#[derive(Default)]
+pub struct Env {
+    /// Variables in scope, such as the parameters of a function call.
+    pub vars: HashMap<String, Value>,
+    /// How many calls to user-defined functions are in progress.
+    pub depth: usize,
+    /// When to give up with `EvalError::Timeout`, and the time limit
+    /// that it records.
+    pub deadline: Option<(Instant, Duration)>,
+    /// Evaluate as if `:modulus` and `:ring` were off, as for an exponent,
+    /// which is an integer rather than a residue.
+    pub ignore_modulus: bool,
+}
+
+
+/// The settings to evaluate with in `env`.
+fn effective_settings<'a>(calculator: &'a Calculator, env: &Env) -> Cow<'a, Settings> {
+    if env.ignore_modulus && calculator.settings.reduction().is_some() {
+        Cow::Owned(Settings { modulus: None, ring: None, ..calculator.settings.clone() })
+    } else {
+        Cow::Borrowed(&calculator.settings)
+    }
+}
+
+
+/// Evaluate an expression in the current numeric mode. `#N` references
+/// are resolved against the earlier results of the session, and `ans` is
+/// the latest of them.
+pub fn evaluate(expr: &Expr, calculator: &Calculator) -> Result<Value, EvalError> {
+    evaluate_env(expr, calculator, &Env::default())
+}
+
+
+/// Evaluate an expression, giving up with `EvalError::Timeout` if it takes
+/// longer than `timeout`.
+pub fn evaluate_with_timeout(expr: &Expr,
+                             calculator: &Calculator,
+                             timeout: Duration) -> Result<Value, EvalError> {
+    let env = Env { deadline: Some((Instant::now() + timeout, timeout)), ..Env::default() };
+    evaluate_env(expr, calculator, &env)
+}
+
+
+/// Whether `a` and `b` are probably the same function of `vars`: they agree
+/// at `samples` points, with each variable a pseudo-random integer from
+/// -100 to 100, evaluated in integer mode with the default settings. They
+/// agree at a point if they give equal values or both fail. A point at
+/// which either divides by zero is skipped, so if every point is skipped
+/// they are reported equivalent. The points are the same on every call.
+pub fn equivalent(a: &Expr, b: &Expr, vars: &[&str], samples: usize) -> bool {
+    let calculator = Calculator::new();
+    // A xorshift generator, which is plenty for choosing sample points.
+    let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+    let mut next = || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        (state % 201) as i32 - 100
+    };
+    (0..samples).all(|_| {
+        let env = Env {
+            vars: vars.iter().map(|var| (String::from(*var), Value::Int(next()))).collect(),
+            ..Env::default()
+        };
+        match (evaluate_env(a, &calculator, &env), evaluate_env(b, &calculator, &env)) {
+            (Err(EvalError::DivisionByZero), _) | (_, Err(EvalError::DivisionByZero)) => true,
+            (Ok(l), Ok(r)) => l.compare(r) == Some(Ordering::Equal),
+            (Err(_), Err(_)) => true,
+            _ => false,
+        }
+    })
+}
+
+
+/// Evaluate `expr` at `count` evenly spaced values of the variable `var`,
+/// from `lo` to `hi` inclusive, for `:plot`. Each sample pairs the value of
+/// `var` with the result as a float, or `None` if evaluation failed there
+/// or gave a boolean, so that one bad point does not spoil the rest.
+pub fn sample(expr: &Expr,
+              var: &str,
+              lo: f64,
+              hi: f64,
+              count: usize,
+              calculator: &Calculator) -> Vec<(f64, Option<f64>)> {
+    let step = if count > 1 { (hi - lo) / (count - 1) as f64 } else { 0.0 };
+    (0..count).map(|i| {
+        let x = lo + step * i as f64;
+        let env = Env {
+            vars: iter::once((String::from(var), Value::Float(x))).collect(),
+            ..Env::default()
+        };
+        let y = match evaluate_env(expr, calculator, &env) {
+            Ok(value) if !value.is_bool() => Some(value.to_f64()).filter(|y| y.is_finite()),
+            _ => None,
+        };
+        (x, y)
+    }).collect()
+}
+
+
+/// The steps of evaluating `expr` one operation at a time, for `:trace`.
+/// The first is the expression itself, and each after it replaces the
+/// leftmost operation whose operands are all numbers with its value, until
+/// the last, which is the result as the REPL shows it: `2 * 3 + 1` gives
+/// `2 * 3 + 1`, `6 + 1` and `7`. Looking up a variable is a step of its
+/// own, and a function call is a single step once its arguments are
+/// numbers.
+pub fn trace(mut expr: Expr, calculator: &Calculator) -> Result<Vec<String>, EvalError> {
+    let env = Env::default();
+    let mut steps = vec![expr.to_string()];
+    while reduce_within(&mut expr, calculator, &env)? {
+        steps.push(expr.to_string());
+    }
+    let result = calculator.display(evaluate_env(&expr, calculator, &env)?);
+    if steps.last() != Some(&result) {
+        steps.push(result);
+    }
+    Ok(steps)
+}
+
+
+/// Reduce the leftmost operation below the top of `expr` whose operands
+/// are all numbers, as for `trace`. Returns whether there was one.
+fn reduce_within(expr: &mut Expr, calculator: &Calculator, env: &Env) -> Result<bool, EvalError> {
+    use Expr::*;
+    match *expr {
+        Pow(ref mut l, ref mut r) => {
+            let exact = Env { vars: env.vars.clone(), ignore_modulus: true, ..*env };
+            Ok(reduce_step(l, calculator, env)? || reduce_step(r, calculator, &exact)?)
+        },
+        Plus(ref mut l, ref mut r) | Minus(ref mut l, ref mut r) | Times(ref mut l, ref mut r) |
+        Divide(ref mut l, ref mut r) | Modulo(ref mut l, ref mut r) |
+        Compare(_, ref mut l, ref mut r) =>
+            Ok(reduce_step(l, calculator, env)? || reduce_step(r, calculator, env)?),
+        Negate(ref mut operand) | Factorial(ref mut operand) =>
+            reduce_step(operand, calculator, env),
+        // The first argument of `default` may fail, so it is one step.
+        Call(ref name, _) if is_default(name, calculator) => Ok(false),
+        Call(_, ref mut args) => {
+            for arg in args.iter_mut() {
+                if reduce_step(arg, calculator, env)? {
+                    return Ok(true);
+                }
+            }
+            Ok(false)
+        },
+        _ => Ok(false),
+    }
+}
+
+
+/// As `reduce_within`, but replacing `expr` itself with its value if its
+/// operands are all numbers. A boolean has no literal to replace it with,
+/// so a comparison is left for the operation above it to evaluate.
+fn reduce_step(expr: &mut Expr, calculator: &Calculator, env: &Env) -> Result<bool, EvalError> {
+    use Expr::*;
+    if let Number(_) | Float(_) | Typed(..) | Quantity(..) = *expr {
+        return Ok(false);
+    }
+    if reduce_within(expr, calculator, env)? {
+        return Ok(true);
+    }
+    *expr = match evaluate_operand(expr, calculator, env)? {
+        Value::Int(n) | Value::Quotient(n, ..) | Value::Remainder(n, ..) => Number(n),
+        Value::Typed(n, width) => Typed(n, width),
+        Value::Float(x) => Float(x),
+        Value::Quantity(x, units) => Quantity(x, units),
+        Value::Bool(_) => return Ok(false),
+    };
+    Ok(true)
+}
+
+
+/// Evaluate an operand of an operator or function, which uses only the
+/// quotient of a division with a remainder.
+fn evaluate_operand(expr: &Expr, calculator: &Calculator, env: &Env) -> Result<Value, EvalError> {
+    evaluate_env(expr, calculator, env).map(Value::truncated)
+}
+
+
+/// Evaluate an expression with the variables in `env` bound.
+///
+/// Under `:modulus`, every integer result, including that of each
+/// operation within the expression, is reduced modulo the modulus, to a
+/// value from 0 to one less than it. So `3 - 5` is 5 modulo 7. Division
+/// multiplies by the inverse of the divisor, and is an error if the divisor
+/// has a factor in common with the modulus. An exponent is evaluated
+/// exactly, since it counts multiplications rather than being a residue,
+/// but variables and function arguments hold reduced values, including
+/// the exponent of `pow ( x , y )`. `!` is computed exactly and then
+/// reduced, so it still overflows beyond `12 !`.
+///
+/// `:ring` reduces in the same way, modulo a power of two, so that integers
+/// wrap the way unsigned machine words do: `255 + 1` is 0 in an 8-bit ring.
+/// Division there truncates, as the hardware's does, instead of using an
+/// inverse.
+pub fn evaluate_env(expr: &Expr, calculator: &Calculator, env: &Env) -> Result<Value, EvalError> {
+    let value = evaluate_unreduced(expr, calculator, env)?;
+    match (value, effective_settings(calculator, env).reduction()) {
+        (Value::Int(n), Some(modulus)) => Ok(Value::Int(reduce(i64::from(n), modulus))),
+        _ => Ok(value),
+    }
+}
+
+
+fn evaluate_unreduced(expr: &Expr, calculator: &Calculator, env: &Env) -> Result<Value, EvalError> {
+    use EvalError::*;
+    use Expr::*;
+    debug!("evaluate {}", to_sexpr(expr));
+    if let Some((deadline, timeout)) = env.deadline {
+        if Instant::now() > deadline {
+            return Err(Timeout(timeout));
+        }
+    }
+    let settings = &*effective_settings(calculator, env);
+    match *expr {
+        Number(n) => match settings.numbers {
+            Numbers::Integer | Numbers::Mixed => Ok(Value::Int(n)),
+            Numbers::Float => Ok(Value::Float(f64::from(n))),
+        },
+        Float(x) => match settings.numbers {
+            Numbers::Integer => Err(FloatInIntegerMode(x)),
+            Numbers::Float | Numbers::Mixed => Ok(Value::Float(x)),
+        },
+        Typed(n, width) =>
+            if width.contains(n) {
+                Ok(Value::Typed(n, width))
+            } else {
+                Err(Overflow(width))
+            },
+        Quantity(x, units) => Ok(Value::Quantity(x, units)),
+        Previous(index) =>
+            if index == 0 || index > calculator.results.len() {
+                Err(NoSuchResult(index, calculator.results.len()))
+            } else {
+                Ok(calculator.results[index - 1])
+            },
+        Var(ref name) => {
+            if let Some(&value) = env.vars.get(name)
+                    .or_else(|| calculator.constants.get(name))
+                    .or_else(|| calculator.vars.get(name)) {
+                return Ok(value);
+            }
+            // Unless a variable has the name, `ans` is the latest result.
+            if name == "ans" {
+                return calculator.results.last().copied().ok_or(NoPreviousResult);
+            }
+            if let Some(x) = constant(name) {
+                return evaluate_env(&Float(x), calculator, env);
+            }
+            if !settings.lenient_vars {
+                return Err(UnknownVariable(name.clone()));
+            }
+            calculator.warnings.borrow_mut().push(Warning::UnknownVariable(name.clone()));
+            Ok(Value::Int(0))
+        },
+        Memory => match calculator.memory {
+            Some(value) => Ok(value),
+            None => evaluate_env(&Number(0), calculator, env),
+        },
+        Pow(ref e_left, ref e_right) => {
+            let base = evaluate_operand(e_left, calculator, env)?;
+            let exponent = if settings.reduction().is_some() {
+                let exact = Env { vars: env.vars.clone(), ignore_modulus: true, ..*env };
+                evaluate_operand(e_right, calculator, &exact)?
+            } else {
+                evaluate_operand(e_right, calculator, env)?
+            };
+            power(base, exponent, settings)
+        },
+        Plus(ref e_left, ref e_right) =>
+            arithmetic(evaluate_operand(e_left, calculator, env)?,
+                       evaluate_operand(e_right, calculator, env)?,
+                       i64::checked_add, |l, r| l + r, Units::same, settings),
+        Minus(ref e_left, ref e_right) =>
+            arithmetic(evaluate_operand(e_left, calculator, env)?,
+                       evaluate_operand(e_right, calculator, env)?,
+                       i64::checked_sub, |l, r| l - r, Units::same, settings),
+        Times(ref e_left, ref e_right) =>
+            arithmetic(evaluate_operand(e_left, calculator, env)?,
+                       evaluate_operand(e_right, calculator, env)?,
+                       i64::checked_mul, |l, r| l * r, Units::times, settings),
+        Divide(ref e_left, ref e_right) => {
+            let l_value = evaluate_operand(e_left, calculator, env)?;
+            let r_value = evaluate_operand(e_right, calculator, env)?;
+            if let (Value::Int(l), Value::Int(r), Some(modulus)) =
+                    (l_value, r_value, settings.modulus) {
+                return modular_divide(l, r, modulus);
+            }
+            let quotient = division(l_value, r_value, i64::checked_div, |l, r| l / r,
+                                    Units::over, settings)?;
+            match (l_value, r_value, quotient) {
+                (Value::Int(l), Value::Int(r), Value::Int(q))
+                    if settings.quotient == Quotient::DivMod ||
+                       (settings.quotient != Quotient::Truncate && l % r != 0) =>
+                        Ok(Value::Quotient(q, l % r, r)),
+                _ => Ok(quotient),
+            }
+        },
+        Modulo(ref e_left, ref e_right) => {
+            let l_value = evaluate_operand(e_left, calculator, env)?;
+            let r_value = evaluate_operand(e_right, calculator, env)?;
+            let remainder = division(l_value, r_value, i64::checked_rem, |l, r| l % r,
+                                     Units::same, settings)?;
+            match (l_value, r_value, remainder) {
+                (Value::Int(l), Value::Int(r), Value::Int(rem))
+                    if settings.quotient == Quotient::DivMod && settings.modulus.is_none() =>
+                        l.checked_div(r).map(|q| Value::Remainder(rem, q, r))
+                                        .ok_or(Overflow(IntType::I32)),
+                _ => Ok(remainder),
+            }
+        },
+        Negate(ref operand) => match evaluate_operand(operand, calculator, env)? {
+            Value::Int(n) | Value::Quotient(n, ..) | Value::Remainder(n, ..) =>
+                n.checked_neg().map(Value::Int).ok_or(Overflow(IntType::I32)),
+            Value::Typed(n, width) => n.checked_neg().filter(|&n| width.contains(n))
+                                              .map(|n| Value::Typed(n, width))
+                                              .ok_or(Overflow(width)),
+            Value::Float(x) => Ok(Value::Float(-x)),
+            Value::Quantity(x, units) => Ok(Value::Quantity(-x, units)),
+            value @ Value::Bool(_) => Err(NotANumber(value)),
+        },
+        Factorial(ref operand) => factorial(evaluate_operand(operand, calculator, env)?, settings),
+        Compare(comparison, ref e_left, ref e_right) => {
+            let l_value = evaluate_operand(e_left, calculator, env)?;
+            let r_value = evaluate_operand(e_right, calculator, env)?;
+            if l_value.is_bool() != r_value.is_bool() {
+                return Err(NotANumber(if l_value.is_bool() { l_value } else { r_value }));
+            }
+            if l_value.units() != r_value.units() {
+                return Err(IncompatibleUnits(l_value.units(), r_value.units()));
+            }
+            Ok(Value::Bool(compare(comparison, l_value.compare(r_value))))
+        },
+        Call(ref name, ref args) if is_default(name, calculator) => {
+            if args.len() != 2 {
+                return Err(ArgumentCount(name.clone(), 2));
+            }
+            match evaluate_operand(&args[0], calculator, env) {
+                Err(UnknownVariable(_)) => evaluate_operand(&args[1], calculator, env),
+                result => result,
+            }
+        },
+        Call(ref name, ref args) => {
+            let values = args.iter()
+                .map(|arg| evaluate_operand(arg, calculator, env))
+                .collect::<Result<Vec<Value>, EvalError>>()?;
+            call_function(name, &values, calculator, env)
+        },
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use calculator::Calculator;
+    use lexer::lex;
+    use parser::{parse, Statement};
+
+    /// Run each of `lines` as the REPL would and then evaluate `input`, in
+    /// a new session. The lines may be `:` or memory commands, definitions,
+    /// assignments or expressions, and must succeed.
+    fn run(lines: &[&str], input: &str) -> Result<Value, EvalError> {
+        let calculator = session(lines);
+        let expr = calculator.parse(input).ok().expect("the input should parse");
+        evaluate(&expr, &calculator)
+    }
+
+    /// A new session in which each of `lines` has been run, as for `run`.
+    fn session(lines: &[&str]) -> Calculator {
+        let mut calculator = Calculator::new();
+        for line in lines {
+            if line.starts_with(':') {
+                calculator.command(line);
+                continue;
+            }
+            if calculator.memory_command(line).is_some() {
+                continue;
+            }
+            match calculator.parse_statement(line).ok().expect("each line should parse") {
+                Statement::Definition(name, function) => {
+                    calculator.functions.insert(name, function);
+                },
+                Statement::Assignment(names, expr) => {
+                    let value = evaluate(&expr, &calculator).ok().expect("each line should run");
+                    calculator.assign(&names, value).ok().expect("each line should run");
+                },
+                Statement::Expression(expr) => {
+                    let value = evaluate(&expr, &calculator).ok().expect("each line should run");
+                    calculator.record(line, value);
+                },
+            }
+        }
+        calculator
+    }
+
+    #[test]
+    fn divmod_of_min_by_minus_one_overflows() {
+        assert!(matches!(run(&[":divmod on"], "-2147483648 % -1"),
+                         Err(EvalError::Overflow(IntType::I32))));
+        assert!(matches!(run(&[":divmod on"], "7 % 2"), Ok(Value::Remainder(1, 3, 2))));
+    }
+
+    #[test]
+    fn negating_the_most_negative_typed_literal_overflows() {
+        assert!(matches!(run(&[], "- -9223372036854775808i64"),
+                         Err(EvalError::Overflow(IntType::I64))));
+        assert!(matches!(run(&[], "- -128i8"), Err(EvalError::Overflow(IntType::I8))));
+        assert!(matches!(run(&[], "- -127i8"), Ok(Value::Typed(127, IntType::I8))));
+    }
+
+    #[test]
+    fn abs_of_the_most_negative_typed_integer_overflows() {
+        assert!(matches!(run(&[], "abs ( -9223372036854775808i64 )"),
+                         Err(EvalError::Overflow(IntType::I64))));
+        assert!(matches!(run(&[], "abs ( -5i8 )"), Ok(Value::Typed(5, IntType::I8))));
+    }
+
+    #[test]
+    fn superscripts_are_powers() {
+        assert!(matches!(run(&[], "5²"), Ok(Value::Int(25))));
+        assert!(matches!(run(&[], "2³"), Ok(Value::Int(8))));
+    }
+
+    #[test]
+    fn modulo_before_addition() {
+        assert!(matches!(run(&[], "6 + 4 % 3"), Ok(Value::Int(7))));
+        assert!(matches!(run(&[], "7 mod 3"), Ok(Value::Int(1))));
+    }
+
+    #[test]
+    fn result_references_are_bounds_checked() {
+        let mut calculator = Calculator::new();
+        calculator.record("1 + 1", Value::Int(2));
+        let valid = calculator.parse("#1 * 3").ok().expect("the input should parse");
+        assert!(matches!(evaluate(&valid, &calculator), Ok(Value::Int(6))));
+        let out_of_range = calculator.parse("#2").ok().expect("the input should parse");
+        assert!(matches!(evaluate(&out_of_range, &calculator), Err(EvalError::NoSuchResult(2, 1))));
+        let zero = calculator.parse("#0").ok().expect("the input should parse");
+        assert!(matches!(evaluate(&zero, &calculator), Err(EvalError::NoSuchResult(0, 1))));
+    }
+
+    #[test]
+    fn infix_and_rpn_agree() {
+        assert!(matches!(run(&[], "( 3 + 4 ) * 2 - 10 / 5"), Ok(Value::Int(12))));
+        assert!(matches!(run(&[":syntax rpn"], "3 4 + 2 * 10 5 / -"), Ok(Value::Int(12))));
+    }
+
+    #[test]
+    fn non_finite_results_follow_the_nan_setting() {
+        let float = &[":numbers float"];
+        assert!(matches!(run(float, "1.0 / 0.0"),
+                         Err(EvalError::NotFinite(x)) if x == f64::INFINITY));
+        assert!(matches!(run(float, "sqrt ( - 1 )"), Err(EvalError::NotFinite(x)) if x.is_nan()));
+        let allow = &[":numbers float", ":nan allow"];
+        assert!(matches!(run(allow, "1.0 / 0.0"), Ok(Value::Float(x)) if x == f64::INFINITY));
+        assert!(matches!(run(allow, "sqrt ( - 1 )"), Ok(Value::Float(x)) if x.is_nan()));
+        assert!(matches!(run(&[], "1.5"), Err(EvalError::FloatInIntegerMode(_))));
+    }
+
+    #[test]
+    fn square_roots() {
+        assert!(matches!(run(&[], "sqrt ( 16 )"), Ok(Value::Int(4))));
+        assert!(matches!(run(&[], "sqrt ( 17 )"), Ok(Value::Int(4))));
+        assert!(matches!(run(&[], "sqrt ( - 4 )"), Err(EvalError::NotFinite(x)) if x.is_nan()));
+        assert!(matches!(run(&[":numbers float"], "sqrt ( 2.25 )"),
+                         Ok(Value::Float(x)) if x == 1.5));
+        assert!(matches!(run(&[":numbers mixed"], "sqrt ( 16 )"), Ok(Value::Int(4))));
+        assert!(matches!(run(&[":numbers mixed"], "sqrt ( 2 )"),
+                         Ok(Value::Float(x)) if x == 2f64.sqrt()));
+        assert!(matches!(run(&[":numbers mixed", ":nan allow"], "sqrt ( - 4 )"),
+                         Ok(Value::Float(x)) if x.is_nan()));
+        assert!(matches!(run(&[":numbers float"], "sqrt ( 4 m )"), Err(EvalError::UnitExponent)));
+    }
+
+    #[test]
+    fn variadic_sum_product_and_avg() {
+        assert!(matches!(run(&[], "sum ( 1 , 2 , 3 , 4 )"), Ok(Value::Int(10))));
+        assert!(matches!(run(&[], "product ( 2 , 3 , 4 )"), Ok(Value::Int(24))));
+        assert!(matches!(run(&[], "avg ( 1 , 2 , 4 )"), Ok(Value::Int(2))));
+        assert!(matches!(run(&[":numbers float"], "avg ( 1 , 2 , 4 )"),
+                         Ok(Value::Float(x)) if (x - 7.0 / 3.0).abs() < 1e-12));
+        for function in ["sum", "product", "avg"] {
+            assert!(matches!(run(&[], &format!("{} ( )", function)),
+                             Err(EvalError::NoArguments(ref name)) if name == function));
+        }
+    }
+
+    #[test]
+    fn clamp_keeps_a_value_in_its_range() {
+        assert!(matches!(run(&[], "clamp ( - 3 , 0 , 10 )"), Ok(Value::Int(0))));
+        assert!(matches!(run(&[], "clamp ( 4 , 0 , 10 )"), Ok(Value::Int(4))));
+        assert!(matches!(run(&[], "clamp ( 12 , 0 , 10 )"), Ok(Value::Int(10))));
+        assert!(matches!(run(&[], "clamp ( 4 , 10 , 0 )"),
+                         Err(EvalError::EmptyRange(Value::Int(10), Value::Int(0)))));
+    }
+
+    #[test]
+    fn defined_functions_can_be_called() {
+        let double = &["f ( x ) = x * 2 + 1"];
+        assert!(matches!(run(double, "f ( 4 )"), Ok(Value::Int(9))));
+        assert!(matches!(run(double, "f ( f ( 1 ) ) - 1"), Ok(Value::Int(6))));
+        assert!(matches!(run(double, "f ( 1 , 2 )"), Err(EvalError::ArgumentCount(_, 1))));
+    }
+
+    #[test]
+    fn runaway_recursion_hits_the_limit() {
+        // A low limit, as the test runs with a smaller stack than `main`.
+        let lines = &[":recursion-limit 20", "r ( x ) = r ( x + 1 )"];
+        assert!(matches!(run(lines, "r ( 0 )"), Err(EvalError::RecursionLimitExceeded(20))));
+    }
+
+    #[test]
+    fn typed_integers_check_their_width() {
+        assert!(matches!(run(&[], "300i8"), Err(EvalError::Overflow(IntType::I8))));
+        assert!(matches!(run(&[], "5i8 + 3i8"), Ok(Value::Typed(8, IntType::I8))));
+        assert!(matches!(run(&[], "100i8 + 100i8"), Err(EvalError::Overflow(IntType::I8))));
+        assert!(matches!(run(&[], "5i8 + 3i16"),
+                         Err(EvalError::MismatchedWidths(IntType::I8, IntType::I16))));
+    }
+
+    #[test]
+    fn negative_literals_and_subtraction() {
+        assert!(matches!(run(&[], "-5 + 3"), Ok(Value::Int(-2))));
+        assert!(matches!(run(&[], "3 - 5"), Ok(Value::Int(-2))));
+        assert!(matches!(run(&[], "3 - - 5"), Ok(Value::Int(8))));
+        assert!(matches!(run(&[], "-2 ^ 2"), Ok(Value::Int(4))));
+        assert!(matches!(run(&[], "- 2 ^ 2"), Ok(Value::Int(-4))));
+    }
+
+    #[test]
+    fn leading_plus_is_a_no_op() {
+        assert!(matches!(run(&[], "+ 5"), Ok(Value::Int(5))));
+        assert!(matches!(run(&[], "2 * + 3"), Ok(Value::Int(6))));
+        assert!(matches!(run(&[], "+ ( 1 - 4 )"), Ok(Value::Int(-3))));
+    }
+
+    #[test]
+    fn spaced_comparisons() {
+        assert!(matches!(run(&[], "1 <= 2"), Ok(Value::Bool(true))));
+        assert!(matches!(run(&[], "2 >= 3"), Ok(Value::Bool(false))));
+        assert!(matches!(run(&[], "2 * 2 == 4"), Ok(Value::Bool(true))));
+        assert!(matches!(run(&[], "2 != 2"), Ok(Value::Bool(false))));
+    }
+
+    #[test]
+    fn lenient_mode_reads_unknown_variables_as_zero() {
+        let calculator = session(&[":lenient-vars on"]);
+        let expr = calculator.parse("x + 5").ok().expect("the input should parse");
+        assert!(matches!(evaluate(&expr, &calculator), Ok(Value::Int(5))));
+        assert!(matches!(calculator.warnings.borrow()[..],
+                         [Warning::UnknownVariable(ref name)] if name == "x"));
+        assert!(matches!(run(&[], "x + 5"),
+                         Err(EvalError::UnknownVariable(ref name)) if name == "x"));
+    }
+
+    #[test]
+    fn factorial_overflows_past_twelve() {
+        assert!(matches!(run(&[], "12 !"), Ok(Value::Int(479_001_600))));
+        assert!(matches!(run(&[], "13 !"),
+                         Err(EvalError::FactorialOverflow(13, 12, IntType::I32))));
+        assert!(matches!(run(&[], "0 !"), Ok(Value::Int(1))));
+    }
+
+    #[test]
+    fn comparisons_are_booleans() {
+        assert!(matches!(run(&[], "1 < 2"), Ok(Value::Bool(true))));
+        // Comparisons bind more loosely than arithmetic.
+        assert!(matches!(run(&[], "1 < 2 + 3"), Ok(Value::Bool(true))));
+        assert!(matches!(run(&[], "( 1 < 2 ) + 3"), Err(EvalError::NotANumber(Value::Bool(true)))));
+    }
+
+    #[test]
+    fn memory_accumulates_results() {
+        assert!(matches!(run(&["5", "M+", "3", "M+"], "MR"), Ok(Value::Int(8))));
+        assert!(matches!(run(&["5", "M+", "3", "M-"], "MR"), Ok(Value::Int(2))));
+        assert!(matches!(run(&["5", "M+", "MC"], "MR"), Ok(Value::Int(0))));
+    }
+
+    #[test]
+    fn assigning_a_constant_shadows_it_with_a_warning() {
+        let calculator = session(&["pi = 3"]);
+        assert!(matches!(calculator.warnings.borrow()[..],
+                         [Warning::ShadowsConstant(ref name)] if name == "pi"));
+        let expr = calculator.parse("pi * 2").ok().expect("the input should parse");
+        assert!(matches!(evaluate(&expr, &calculator), Ok(Value::Int(6))));
+        assert!(matches!(run(&[":numbers float"], "pi"),
+                         Ok(Value::Float(x)) if x == std::f64::consts::PI));
+    }
+
+    #[test]
+    fn sign_of_negative_zero_and_positive() {
+        assert!(matches!(run(&[], "sign ( - 3 )"), Ok(Value::Int(-1))));
+        assert!(matches!(run(&[], "sign ( 0 )"), Ok(Value::Int(0))));
+        assert!(matches!(run(&[], "sign ( 7 )"), Ok(Value::Int(1))));
+        assert!(matches!(run(&[":numbers float"], "sign ( 0 )"), Ok(Value::Float(x)) if x == 0.0));
+        assert!(matches!(run(&[], "abs ( - 3 )"), Ok(Value::Int(3))));
+    }
+
+    #[test]
+    fn pow_matches_the_operator() {
+        assert!(matches!(run(&[], "pow ( 2 , 10 )"), Ok(Value::Int(1024))));
+        assert!(matches!(run(&[], "pow ( 2 , 10 ) == 2 ^ 10"), Ok(Value::Bool(true))));
+        assert!(matches!(run(&[], "2 ^ 31"), Err(EvalError::Overflow(IntType::I32))));
+        assert!(matches!(run(&[], "pow ( 2 , 31 )"), Err(EvalError::Overflow(IntType::I32))));
+        assert!(matches!(run(&[], "pow ( 2 )"), Err(EvalError::ArgumentCount(_, 2))));
+    }
+
+    #[test]
+    fn comma_as_the_decimal_point() {
+        let lines = &[":decimal-sep ,", ":numbers float"];
+        assert!(matches!(run(lines, "3,14 + 1"), Ok(Value::Float(x)) if (x - 4.14).abs() < 1e-12));
+        assert!(matches!(run(lines, "sum ( 1 ; 2,5 )"), Ok(Value::Float(x)) if x == 3.5));
+    }
+
+    /// A logger which formats each message and counts them, for checking
+    /// that `--verbose` logging runs without changing results.
+    struct CountingLogger(std::sync::atomic::AtomicUsize);
+
+    impl log::Log for CountingLogger {
+        fn enabled(&self, _: &log::Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &log::Record) {
+            assert!(!record.args().to_string().is_empty());
+            self.0.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        }
+
+        fn flush(&self) {}
+    }
+
+    static LOGGER: CountingLogger = CountingLogger(std::sync::atomic::AtomicUsize::new(0));
+
+    #[test]
+    fn verbose_logging_does_not_change_results() {
+        let inputs = ["1 + 2 * 3 ^ 2", "sum ( 1 , - 4 , 6 ) ! - 10 % 4", "( 1 < 2 ) == ( 3 > 2 )"];
+        let results = || -> Vec<String> {
+            inputs.iter().map(|input| match run(&[], input) {
+                Ok(value) => Calculator::new().display(value),
+                Err(_) => panic!("{} should evaluate", input),
+            }).collect()
+        };
+        let quiet = results();
+        log::set_logger(&LOGGER).expect("no other test should set a logger");
+        log::set_max_level(log::LevelFilter::Debug);
+        let verbose = results();
+        log::set_max_level(log::LevelFilter::Off);
+        assert_eq!(quiet, verbose);
+        assert!(LOGGER.0.load(std::sync::atomic::Ordering::SeqCst) > 0);
+    }
+
+    #[test]
+    fn mixed_quotients_keep_their_remainder() {
+        let calculator = session(&[":quotient mixed"]);
+        let seven_halves = run(&[":quotient mixed"], "7 / 2").ok().expect("7 / 2 should evaluate");
+        assert!(matches!(seven_halves, Value::Quotient(3, 1, 2)));
+        assert_eq!(calculator.display(seven_halves), "3 1/2");
+        let negative = run(&[":quotient mixed"], "-7 / 2").ok().expect("-7 / 2 should evaluate");
+        assert!(matches!(negative, Value::Quotient(-3, -1, 2)));
+        assert_eq!(calculator.display(negative), "-3 1/2");
+        assert!(matches!(run(&[":quotient mixed"], "6 / 3"), Ok(Value::Int(2))));
+    }
+
+    #[test]
+    fn inf_and_nan_follow_ieee_rules() {
+        let lines = &[":numbers float", ":nan allow"];
+        assert!(matches!(run(lines, "inf + 1"), Ok(Value::Float(x)) if x == f64::INFINITY));
+        assert!(matches!(run(lines, "1 / inf"), Ok(Value::Float(x)) if x == 0.0));
+        assert!(matches!(run(lines, "nan == nan"), Ok(Value::Bool(false))));
+        assert!(matches!(run(lines, "inf - inf"), Ok(Value::Float(x)) if x.is_nan()));
+        assert!(matches!(run(&[":numbers float"], "inf + 1"), Err(EvalError::NotFinite(_))));
+    }
+
+    #[test]
+    fn slow_evaluations_time_out() {
+        // Each function calls the one before twice, so `f30` makes 2^30 calls.
+        let mut lines = vec![String::from("f0 ( x ) = x")];
+        for n in 1..=30 {
+            lines.push(format!("f{} ( x ) = f{} ( x ) + f{} ( x )", n, n - 1, n - 1));
+        }
+        let lines: Vec<&str> = lines.iter().map(String::as_str).collect();
+        let calculator = session(&lines);
+        let timeout = Duration::from_millis(20);
+        let slow = calculator.parse("f30 ( 1 )").ok().expect("the call should parse");
+        assert!(matches!(evaluate_with_timeout(&slow, &calculator, timeout),
+                         Err(EvalError::Timeout(t)) if t == timeout));
+        let quick = calculator.parse("f3 ( 1 )").ok().expect("the call should parse");
+        assert!(matches!(evaluate_with_timeout(&quick, &calculator, Duration::from_secs(60)),
+                         Ok(Value::Int(8))));
+    }
+
+    #[test]
+    fn defined_constants_cannot_be_reassigned() {
+        let lines = &[":numbers float", ":define GOLDEN 1.618"];
+        assert!(matches!(run(lines, "GOLDEN * 2"), Ok(Value::Float(x)) if x == 3.236));
+        let mut calculator = session(lines);
+        let names = vec![String::from("x"), String::from("GOLDEN")];
+        assert!(matches!(calculator.assign(&names, Value::Float(3.0)),
+                         Err(EvalError::ConstantAssignment(ref name)) if name == "GOLDEN"));
+        assert!(!calculator.vars.contains_key("x"));
+        let expr = calculator.parse("GOLDEN").ok().expect("the name should parse");
+        assert!(matches!(evaluate(&expr, &calculator), Ok(Value::Float(x)) if x == 1.618));
+    }
+
+    #[test]
+    fn function_arguments_may_be_any_expression() {
+        assert!(matches!(run(&[], "abs ( min ( - 3 , 2 ) )"), Ok(Value::Int(3))));
+        assert!(matches!(run(&[], "sqrt ( 3 * 3 + 4 * 4 )"), Ok(Value::Int(5))));
+        assert!(matches!(run(&[], "max ( 1 + 2 , 2 * ( 3 - 1 ) )"), Ok(Value::Int(4))));
+    }
+
+    #[test]
+    fn scale_suffixes_multiply_in_float_mode() {
+        let float = &[":numbers float"];
+        assert!(matches!(run(float, "5 k + 500"), Ok(Value::Float(x)) if x == 5500.0));
+        assert!(matches!(run(float, "1.5 M"), Ok(Value::Float(x)) if x == 1_500_000.0));
+        assert!(matches!(run(float, "2 G"), Ok(Value::Float(x)) if x == 2e9));
+    }
+
+    #[test]
+    fn integers_are_promoted_to_floats_when_mixed() {
+        let mixed = &[":numbers mixed"];
+        assert!(matches!(run(mixed, "1 + 2.0"), Ok(Value::Float(x)) if x == 3.0));
+        assert!(matches!(run(mixed, "4 / 2"), Ok(Value::Int(2))));
+        assert!(matches!(run(mixed, "4.0 / 2"), Ok(Value::Float(x)) if x == 2.0));
+        assert_eq!(session(mixed).display(Value::Float(3.0)), "3.0");
+    }
+
+    #[test]
+    fn undo_restores_the_previous_value() {
+        assert!(matches!(run(&["x = 1", "x = 2", ":undo"], "x"), Ok(Value::Int(1))));
+        assert!(matches!(run(&["x = 1", ":undo"], "x"), Err(EvalError::UnknownVariable(_))));
+    }
+
+    #[test]
+    fn degrees_minutes_and_seconds_make_decimal_degrees() {
+        let float = &[":numbers float"];
+        assert!(matches!(run(float, "12 d 30 m"), Ok(Value::Float(x)) if x == 12.5));
+        assert!(matches!(run(float, "12 d 30 m 36 s"), Ok(Value::Float(x)) if x == 12.51));
+        assert!(matches!(run(float, "12 d 30 m + 1"), Ok(Value::Float(x)) if x == 13.5));
+    }
+
+    #[test]
+    fn long_chains_evaluate_from_the_left() {
+        assert!(matches!(run(&[], "64 / 4 / 2 / 2"), Ok(Value::Int(4))));
+        assert!(matches!(run(&[], "100 - 10 - 20 - 30"), Ok(Value::Int(40))));
+        assert!(matches!(run(&[], "1000 / 2 / 5 / 10"), Ok(Value::Int(10))));
+    }
+
+    #[test]
+    fn ans_is_the_latest_result() {
+        assert!(matches!(run(&["6 * 7"], "ans + 1"), Ok(Value::Int(43))));
+        assert!(matches!(run(&["6 * 7", "2 + 2"], "ans"), Ok(Value::Int(4))));
+        assert!(matches!(run(&["6 * 7", "ans = 5"], "ans"), Ok(Value::Int(5))));
+        assert!(matches!(run(&[], "ans"), Err(EvalError::NoPreviousResult)));
+    }
+
+    #[test]
+    fn results_are_reduced_by_the_modulus() {
+        assert!(matches!(run(&[":modulus 7"], "2 ^ 30 + 1"), Ok(Value::Int(2))));
+        assert!(matches!(run(&[":modulus 1000000007"], "2 ^ 30 + 1"), Ok(Value::Int(73741818))));
+        assert!(matches!(run(&[":modulus 7"], "2 - 5"), Ok(Value::Int(4))));
+        assert!(matches!(run(&[":modulus 7"], "3 / 2"), Ok(Value::Int(5))));
+        assert!(matches!(run(&[":modulus 6"], "1 / 2"), Err(EvalError::NoInverse(2, 6))));
+    }
+
+    #[test]
+    fn equivalence_is_checked_at_sample_points() {
+        let expr = |input| parse(lex(input)).ok().expect("the input should parse");
+        assert!(equivalent(&expr("x * 2"), &expr("x + x"), &["x"], 20));
+        assert!(!equivalent(&expr("x * 2"), &expr("x + 1"), &["x"], 20));
+        assert!(equivalent(&expr("x / x"), &expr("1"), &["x"], 20));
+        assert!(!equivalent(&expr("x - y"), &expr("y - x"), &["x", "y"], 20));
+    }
+
+    #[test]
+    fn trace_shows_each_reduction() {
+        let steps = |input| {
+            let calculator = Calculator::new();
+            let expr = calculator.parse(input).ok().expect("the input should parse");
+            trace(expr, &calculator).ok().expect("the input should evaluate")
+        };
+        assert_eq!(steps("2 * 3 + 1"), vec!["2 * 3 + 1", "6 + 1", "7"]);
+        assert_eq!(steps("( 1 + 2 ) * ( 3 + 4 )"),
+                   vec!["(1 + 2) * (3 + 4)", "3 * (3 + 4)", "3 * 7", "21"]);
+        assert_eq!(steps("5"), vec!["5"]);
+        let calculator = Calculator::new();
+        let expr = calculator.parse("1 + 1 / 0").ok().expect("the input should parse");
+        assert!(matches!(trace(expr, &calculator), Err(EvalError::DivisionByZero)));
+    }
+
+    #[test]
+    fn huge_factorials_and_powers_fail_at_once() {
+        // Without a bignum mode there is no long computation to report progress on.
+        assert!(matches!(run(&[], "100000 !"), Err(EvalError::FactorialOverflow(100000, 12, _))));
+        assert!(matches!(run(&[], "3 ^ 100000"), Err(EvalError::Overflow(IntType::I32))));
+        assert!(matches!(run(&[":numbers float"], "100000 !"), Err(EvalError::NotFinite(_))));
+    }
+
+    #[test]
+    fn divmod_shows_quotient_and_remainder() {
+        let lines = &[":quotient divmod"];
+        let calculator = session(lines);
+        let shown = |input| {
+            calculator.display(run(lines, input).ok().expect("the input should evaluate"))
+        };
+        assert_eq!(shown("17 / 5"), "quotient 3, remainder 2");
+        assert_eq!(shown("17 % 5"), "quotient 3, remainder 2");
+        assert_eq!(shown("-17 / 5"), "quotient -3, remainder -2");
+        assert_eq!(shown("17 / -5"), "quotient -3, remainder 2");
+        assert_eq!(shown("-17 % -5"), "quotient 3, remainder -2");
+        assert_eq!(shown("6 / 3"), "quotient 2, remainder 0");
+        assert!(matches!(run(lines, "17 / 5 + 1"), Ok(Value::Int(4))));
+    }
+
+    #[test]
+    fn built_in_names_ignore_case_but_variables_do_not() {
+        let float = &[":numbers float"];
+        assert!(matches!(run(float, "SQRT ( 4 )"), Ok(Value::Float(x)) if x == 2.0));
+        assert!(matches!(run(&[], "Sqrt ( 9 )"), Ok(Value::Int(3))));
+        assert!(matches!(run(float, "PI"), Ok(Value::Float(x)) if x == std::f64::consts::PI));
+        assert!(matches!(run(&["X = 2"], "x"), Err(EvalError::UnknownVariable(ref name))
+                         if name == "x"));
+    }
+
+    #[test]
+    fn ring_arithmetic_wraps_at_its_width() {
+        let byte = &[":ring 8"];
+        assert!(matches!(run(byte, "255 + 1"), Ok(Value::Int(0))));
+        assert!(matches!(run(byte, "0 - 1"), Ok(Value::Int(255))));
+        assert!(matches!(run(byte, "16 * 16 + 3"), Ok(Value::Int(3))));
+        assert!(matches!(run(&[":ring 16"], "65535 + 2"), Ok(Value::Int(1))));
+    }
+
+    #[test]
+    fn default_replaces_only_undefined_variables() {
+        assert!(matches!(run(&[], "default ( undefined , 5 )"), Ok(Value::Int(5))));
+        assert!(matches!(run(&["x = 3"], "default ( x , 5 )"), Ok(Value::Int(3))));
+        assert!(matches!(run(&[], "default ( 1 / 0 , 5 )"), Err(EvalError::DivisionByZero)));
+    }
+
+    #[test]
+    fn sampling_evaluates_across_the_range() {
+        let calculator = session(&[":numbers float"]);
+        let line = calculator.parse("2 * x + 1").ok().expect("the input should parse");
+        assert_eq!(sample(&line, "x", 0.0, 4.0, 5, &calculator),
+                   vec![(0.0, Some(1.0)), (1.0, Some(3.0)), (2.0, Some(5.0)),
+                        (3.0, Some(7.0)), (4.0, Some(9.0))]);
+        let reciprocal = calculator.parse("1 / x").ok().expect("the input should parse");
+        assert_eq!(sample(&reciprocal, "x", -1.0, 1.0, 3, &calculator),
+                   vec![(-1.0, Some(-1.0)), (0.0, None), (1.0, Some(1.0))]);
+    }
+
+    #[test]
+    fn units_combine_through_arithmetic() {
+        let lines = &[":numbers float"];
+        let calculator = session(lines);
+        let shown = |input| {
+            calculator.display(run(lines, input).ok().expect("the input should evaluate"))
+        };
+        assert_eq!(shown("6 m / 2 s"), "3 m/s");
+        assert_eq!(shown("5 m / 2 s"), "2.5 m/s");
+        assert_eq!(shown("2 m * 3 m"), "6 m^2");
+        let (metres, seconds) = (Units::from_name("m"), Units::from_name("s"));
+        let (metres, seconds) = (metres.expect("m is a unit"), seconds.expect("s is a unit"));
+        assert!(matches!(run(lines, "1 m + 1 s"),
+                         Err(EvalError::IncompatibleUnits(l, r)) if l == metres && r == seconds));
+    }
+}
@@ -0,0 +1,655 @@
+use std::collections::LinkedList;
+use std::convert::TryFrom;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::num::{IntErrorKind, ParseIntError};
+
+use value::{IntType, Units};
+
+/// A floating-point literal. Tokens are used as keys in the operator table,
+/// so floats in tokens are compared and hashed by their bit pattern.
+#[derive(Clone, Copy)]
+pub struct FloatBits(pub f64);
+
+impl PartialEq for FloatBits {
+    fn eq(&self, other: &FloatBits) -> bool {
+        self.0.to_bits() == other.0.to_bits()
+    }
+}
+
+impl Eq for FloatBits {}
+
+impl Hash for FloatBits {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.to_bits().hash(state);
+    }
+}
+
+/// A token of the input. Number literals keep the text they were written
+/// as alongside their value, so that `reprint` can reproduce it.
+#[derive(PartialEq, Eq, Hash)]
+pub enum Token {
+    UnknownToken(String), LexicalError(String), LexicalNumber(i32, String),
+    LexicalFloat(FloatBits, String), LexicalTyped(i64, IntType, String), ResultRef(usize),
+    Identifier(String),
+    /// A number followed by a unit, such as `5 m`.
+    LexicalQuantity(FloatBits, Units, String),
+    /// An integer literal with too many digits for its width.
+    LexicalOverflow(String, IntType),
+    POW, PLUS, MINUS, TIMES, DIVIDE, MODULO, LPAREN, RPAREN, COMMA, ASSIGN,
+    LT, LE, GT, GE, EQ, NE,
+    /// Unary minus, i.e. a `-` with no operand to its left.
+    NEGATE,
+    /// Unary plus, which leaves its operand unchanged.
+    IDENTITY,
+    /// Postfix `!`.
+    FACTORIAL,
+    /// `MR`, the value in the memory register.
+    MEMORY,
+    /// Marks the end of the input; always the last token from `lex`.
+    EOF,
+}
+
+impl fmt::Display for Token {
+    /// Render a token as it would be written in the input. Literals are
+    /// shown as their value.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use Token::*;
+        match *self {
+            UnknownToken(ref text) | LexicalError(ref text) => write!(f, "{}", text),
+            LexicalNumber(n, _) => write!(f, "{}", n),
+            LexicalFloat(FloatBits(x), _) if x.is_nan() => write!(f, "nan"),
+            LexicalFloat(FloatBits(x), _) => write!(f, "{:?}", x),
+            LexicalTyped(n, width, _) => write!(f, "{}{}", n, width),
+            LexicalQuantity(FloatBits(x), units, _) => write!(f, "{:?} {}", x, units),
+            ResultRef(index) => write!(f, "#{}", index),
+            Identifier(ref name) => write!(f, "{}", name),
+            LexicalOverflow(ref number, IntType::I32) => write!(f, "{}", number),
+            LexicalOverflow(ref number, width) => write!(f, "{}{}", number, width),
+            POW => write!(f, "^"),
+            PLUS | IDENTITY => write!(f, "+"),
+            MINUS | NEGATE => write!(f, "-"),
+            TIMES => write!(f, "*"),
+            DIVIDE => write!(f, "/"),
+            MODULO => write!(f, "%"),
+            LPAREN => write!(f, "("),
+            RPAREN => write!(f, ")"),
+            COMMA => write!(f, ","),
+            ASSIGN => write!(f, "="),
+            LT => write!(f, "<"),
+            LE => write!(f, "<="),
+            GT => write!(f, ">"),
+            GE => write!(f, ">="),
+            EQ => write!(f, "=="),
+            NE => write!(f, "!="),
+            FACTORIAL => write!(f, "!"),
+            MEMORY => write!(f, "MR"),
+            EOF => write!(f, "end of input"),
+        }
+    }
+}
+
+
+/// Whether `token` ends an operand, so that a following `-` subtracts
+/// rather than negates.
+pub(crate) fn is_operand(token: Option<&Token>) -> bool {
+    use Token::*;
+    matches!(token,
+             Some(&LexicalNumber(..)) |
+             Some(&LexicalFloat(..)) |
+             Some(&LexicalTyped(..)) |
+             Some(&LexicalQuantity(..)) |
+             Some(&ResultRef(_)) |
+             Some(&Identifier(_)) |
+             Some(&RPAREN) |
+             Some(&FACTORIAL) |
+             Some(&MEMORY))
+}
+
+
+/// Whether parsing an integer failed only because it has too many digits.
+fn is_overflow(error: &ParseIntError) -> bool {
+    matches!(error.kind(), IntErrorKind::PosOverflow | IntErrorKind::NegOverflow)
+}
+
+
+/// Handle `name` if it is part of an angle in degrees, minutes and seconds,
+/// replacing the numbers it applies to at the back of `tokens` with the
+/// angle in degrees. Returns whether it was.
+fn angle_suffix(name: &str, tokens: &mut LinkedList<Token>, angle: &mut Option<(usize, i32)>)
+                -> bool {
+    use Token::*;
+    let unit = match name {
+        "d" => 0,
+        "m" => 1,
+        "s" => 2,
+        _ => return false,
+    };
+    let (value, text) = match tokens.back() {
+        Some(&LexicalNumber(n, ref text)) => (f64::from(n), format!("{} {}", text, name)),
+        Some(&LexicalFloat(FloatBits(x), ref text)) => (x, format!("{} {}", text, name)),
+        _ => return false,
+    };
+    if unit == 0 {
+        tokens.pop_back();
+        tokens.push_back(angle_token(value, text));
+        *angle = Some((tokens.len() - 1, 0));
+        return true;
+    }
+    match *angle {
+        Some((position, latest)) if position + 2 == tokens.len() && latest < unit => {},
+        _ => return false,
+    }
+    tokens.pop_back();
+    if !(0.0..60.0).contains(&value) {
+        tokens.pop_back();
+        tokens.push_back(LexicalError(format!("{} {}", value, name)));
+        *angle = None;
+        return true;
+    }
+    let (degrees, text) = match tokens.pop_back() {
+        Some(LexicalNumber(n, degrees)) => (f64::from(n), format!("{} {}", degrees, text)),
+        Some(LexicalFloat(FloatBits(x), degrees)) => (x, format!("{} {}", degrees, text)),
+        _ => unreachable!("an angle is always a number"),
+    };
+    let part = value / 60f64.powi(unit);
+    let degrees = if degrees.is_sign_negative() { degrees - part } else { degrees + part };
+    tokens.push_back(angle_token(degrees, text));
+    *angle = Some((tokens.len() - 1, unit));
+    true
+}
+
+
+/// An angle in degrees, written as `text`, as a literal: an integer if it
+/// is whole, otherwise a float.
+fn angle_token(degrees: f64, text: String) -> Token {
+    if degrees.fract() == 0.0 && degrees.abs() <= f64::from(i32::MAX) {
+        Token::LexicalNumber(degrees as i32, text)
+    } else {
+        Token::LexicalFloat(FloatBits(degrees), text)
+    }
+}
+
+
+/// The value of the hexadecimal float `text`, such as `0x1.8p1` or
+/// `-0x1p-3`, with `decimal` as its point, or `None` if it is malformed.
+fn hex_float(text: &str, decimal: char) -> Option<f64> {
+    let (negative, text) = match text.strip_prefix('-') {
+        Some(text) => (true, text),
+        None => (false, text),
+    };
+    let (mantissa, exponent) = text.get(2..)?.split_once(['p', 'P'])?;
+    let exponent: i32 = exponent.parse().ok()?;
+    let (whole, fraction) = mantissa.split_once(decimal).unwrap_or((mantissa, ""));
+    if whole.is_empty() && fraction.is_empty() {
+        return None;
+    }
+    let mut value = 0.0;
+    for digit in whole.chars().chain(fraction.chars()) {
+        value = value * 16.0 + f64::from(digit.to_digit(16)?);
+    }
+    let shift = i32::try_from(fraction.len()).ok()?.checked_mul(4)?;
+    let value = value * 2f64.powi(exponent.checked_sub(shift)?);
+    Some(if negative { -value } else { value })
+}
+
+
+/// The factor a scale suffix multiplies the number before it by.
+fn scale(suffix: &str) -> Option<i32> {
+    match suffix {
+        "k" => Some(1_000),
+        "M" => Some(1_000_000),
+        "G" => Some(1_000_000_000),
+        _ => None,
+    }
+}
+
+
+/// Map a Unicode superscript digit to its value, e.g. '²' to 2.
+fn superscript_digit(lexeme: char) -> Option<u32> {
+    match lexeme {
+        '⁰' => Some(0), '¹' => Some(1), '²' => Some(2), '³' => Some(3),
+        '⁴' => Some(4), '⁵' => Some(5), '⁶' => Some(6), '⁷' => Some(7),
+        '⁸' => Some(8), '⁹' => Some(9),
+        _ => None,
+    }
+}
+
+
+/// Split a line of input into tokens.
+///
+/// Superscript digits which follow an operand are read as an exponent, so
+/// `5²` lexes exactly as `5 ^ 2`. A run of adjacent superscripts forms a
+/// single multi-digit exponent, as in written maths: `2¹⁰` is `2 ^ 10` and
+/// `5²³` is `5 ^ 23`. A superscript with no operand before it is an unknown
+/// symbol.
+///
+/// An integer may carry a type suffix giving its width, e.g. `5i8`.
+///
+/// `#N` refers to the Nth result of the session, counting from 1.
+///
+/// A number may be followed by a scale suffix, with or without a space:
+/// `k` for a thousand, `M` for a million and `G` for a billion. The
+/// suffixes are case-sensitive, as in SI, so `5 k` is 5000 but `5 K` is
+/// `5` followed by the variable `K`. An integer which no longer fits is
+/// too large, so `3 G` needs a wider type, `3i64 G`, or `3.0 G` in float
+/// mode. Elsewhere `k`, `M` and `G` are ordinary identifiers.
+///
+/// An angle may be written in degrees, minutes and seconds, as
+/// `12 d 30 m 36 s` or `12d 30m 36s`, and is read as a single literal in
+/// decimal degrees, here 12.51. The minutes and seconds are optional, but must come in
+/// that order after the degrees and be less than 60; each part may be a
+/// decimal. The angle is an integer if it is a whole number of degrees, and
+/// otherwise a float, which needs float or mixed mode. Since it is a
+/// literal it binds tighter than any operator, so `2 * 12 d 30 m` is
+/// `2 * 12.5`, and a negative angle is `-12 d 30 m`. Like the scale
+/// suffixes, `d`, `m` and `s` are identifiers anywhere else.
+///
+/// A hexadecimal float, as in C, is `0x` followed by hexadecimal digits
+/// with an optional point, then `p` and a power of two in decimal: `0x1.8p1`
+/// is 1.5 times 2, or 3.0. The exponent is required, so there are no
+/// hexadecimal integers, and anything else directly after `0x` makes the
+/// whole literal an error.
+///
+/// A number may also be followed by a unit, `m`, `s` or `kg`, to make it a
+/// quantity, such as `5 m`, which binds as tightly as any other literal.
+/// `m` and `s` are taken as minutes and seconds instead when they continue
+/// an angle. A quantity's magnitude is a float, in any `:numbers` mode.
+///
+/// `inf` and `nan` are float literals. Arithmetic on them is still subject
+/// to `:nan`, so `inf + 1` is an error unless `:nan allow` is set.
+///
+/// A `-` with an operand to its left is subtraction. Otherwise it is in a
+/// value position and negates what follows. If a digit follows directly,
+/// the `-` is part of a negative literal instead, so `-5` is the number
+/// -5 while `- 5` is unary minus applied to 5. The two differ only in
+/// precedence: `-2 ^ 2` is 4 but `- 2 ^ 2` is -4. So `3 - 5` and `3-5` are
+/// both subtraction, and `3 - -5` subtracts the literal -5.
+pub fn lex(line: &str) -> LinkedList<Token> {
+    lex_with(line, '.')
+}
+
+
+/// Split a line of input into tokens, with `decimal` as the decimal point.
+///
+/// If `decimal` is `,` then a comma directly after digits continues the
+/// number, so `3,14` is a float, and `;` separates function arguments
+/// instead: `clamp ( 7 ; 1 ; 5 )`. A comma after digits is always read as
+/// a decimal point, even with a space after it, so `sum ( 1, 2 )` is an
+/// error in this mode. `.` is then an unknown symbol.
+pub fn lex_with(line: &str, decimal: char) -> LinkedList<Token> {
+    use Token::*;
+    let mut tokens: LinkedList<Token> = LinkedList::new();
+    let mut int_builder = String::from("");
+    // The position of the angle being written at the back of `tokens`, and
+    // the unit of its latest part: 0 for degrees, 1 for minutes and 2 for
+    // seconds.
+    let mut angle: Option<(usize, i32)> = None;
+    let mut iterator = line.chars().peekable();
+    while let Some(lexeme) = iterator.next() {
+        match lexeme {
+            '0' if (int_builder.is_empty() || int_builder == "-") &&
+                   matches!(iterator.peek(), Some('x' | 'X')) => {
+                let mut text = int_builder + "0";
+                while let Some(&next) = iterator.peek() {
+                    let signed_exponent = (next == '+' || next == '-') &&
+                                          (text.ends_with('p') || text.ends_with('P'));
+                    if !(next.is_ascii_alphanumeric() || next == decimal || signed_exponent) {
+                        break;
+                    }
+                    text.push(next);
+                    iterator.next();
+                }
+                match hex_float(&text, decimal) {
+                    Some(x) => tokens.push_back(LexicalFloat(FloatBits(x), text)),
+                    None => tokens.push_back(LexicalError(text)),
+                }
+                int_builder = String::from("");
+            },
+            _ if lexeme.is_ascii_digit() || (lexeme == decimal && !int_builder.is_empty()) => {
+                if lexeme == decimal && int_builder.contains(decimal) {
+                    int_builder.push(decimal);
+                    tokens.push_back(LexicalError(int_builder));
+                    int_builder = String::from("");
+                    continue;
+                }
+                int_builder.push(lexeme);
+                if !matches!(iterator.peek(), Some(c) if c.is_ascii_digit() || *c == decimal) {
+                    if int_builder.contains(decimal) {
+                        match int_builder.replace(decimal, ".").parse() {
+                            Ok(num) => tokens.push_back(LexicalFloat(FloatBits(num), int_builder)),
+                            Err(_) => tokens.push_back(LexicalError(int_builder)),
+                        };
+                    } else if iterator.peek() == Some(&'i') {
+                        let mut suffix = String::new();
+                        while let Some(&next) = iterator.peek() {
+                            if !next.is_ascii_alphanumeric() {
+                                break;
+                            }
+                            suffix.push(next);
+                            iterator.next();
+                        }
+                        match (int_builder.parse(), IntType::from_suffix(&suffix)) {
+                            (Ok(num), Some(width)) =>
+                                tokens.push_back(LexicalTyped(num, width, int_builder + &suffix)),
+                            (Err(ref error), Some(width)) if is_overflow(error) =>
+                                tokens.push_back(LexicalOverflow(int_builder, width)),
+                            _ => tokens.push_back(LexicalError(int_builder + &suffix)),
+                        };
+                    } else {
+                        match int_builder.parse() {
+                            Ok(num) => tokens.push_back(LexicalNumber(num, int_builder)),
+                            Err(ref error) if is_overflow(error) =>
+                                tokens.push_back(LexicalOverflow(int_builder, IntType::I32)),
+                            Err(_) => tokens.push_back(LexicalError(int_builder)),
+                        };
+                    }
+                    int_builder = String::from("");
+                }
+            },
+            '⁰' | '¹' | '²' | '³' | '⁴' | '⁵' | '⁶' | '⁷' | '⁸' | '⁹' => {
+                let follows_operand = is_operand(tokens.back());
+                let mut exponent = superscript_digit(lexeme).unwrap().to_string();
+                while let Some(digit) = iterator.peek().and_then(|c| superscript_digit(*c)) {
+                    exponent.push_str(&digit.to_string());
+                    iterator.next();
+                }
+                if !follows_operand {
+                    tokens.push_back(UnknownToken(char::to_string(&lexeme)));
+                    continue;
+                }
+                tokens.push_back(POW);
+                match exponent.parse() {
+                    Ok(num) => tokens.push_back(LexicalNumber(num, exponent)),
+                    Err(ref error) if is_overflow(error) =>
+                        tokens.push_back(LexicalOverflow(exponent, IntType::I32)),
+                    Err(_) => tokens.push_back(LexicalError(exponent)),
+                };
+            },
+            '#' => {
+                let mut index = String::new();
+                while let Some(&digit) = iterator.peek() {
+                    if !digit.is_ascii_digit() {
+                        break;
+                    }
+                    index.push(digit);
+                    iterator.next();
+                }
+                if index.is_empty() {
+                    tokens.push_back(UnknownToken(char::to_string(&lexeme)));
+                } else {
+                    match index.parse() {
+                        Ok(num) => tokens.push_back(ResultRef(num)),
+                        Err(_) => tokens.push_back(LexicalError(format!("#{}", index))),
+                    };
+                }
+            },
+            '^' => tokens.push_back(POW),
+            '+' => if is_operand(tokens.back()) {
+                        tokens.push_back(PLUS);
+                    } else {
+                        tokens.push_back(IDENTITY);
+                    },
+            '-' => if is_operand(tokens.back()) {
+                        tokens.push_back(MINUS);
+                    } else if matches!(iterator.peek(), Some(c) if c.is_ascii_digit()) {
+                        int_builder.push(lexeme);
+                    } else {
+                        tokens.push_back(NEGATE);
+                    },
+            '*' => tokens.push_back(TIMES),
+            '/' => tokens.push_back(DIVIDE),
+            '%' => tokens.push_back(MODULO),
+            '(' => tokens.push_back(LPAREN),
+            ')' => tokens.push_back(RPAREN),
+            ',' if decimal != ',' => tokens.push_back(COMMA),
+            ';' if decimal == ',' => tokens.push_back(COMMA),
+            '<' => if iterator.next_if_eq(&'=').is_some() {
+                       tokens.push_back(LE);
+                    } else {
+                       tokens.push_back(LT);
+                    },
+            '>' => if iterator.next_if_eq(&'=').is_some() {
+                       tokens.push_back(GE);
+                    } else {
+                       tokens.push_back(GT);
+                    },
+            '=' => if iterator.next_if_eq(&'=').is_some() {
+                       tokens.push_back(EQ);
+                    } else {
+                       tokens.push_back(ASSIGN);
+                    },
+            '!' => if iterator.next_if_eq(&'=').is_some() {
+                       tokens.push_back(NE);
+                    } else {
+                       tokens.push_back(FACTORIAL);
+                    },
+            'a' ..= 'z' | 'A' ..= 'Z' | '_' => {
+                let mut name = char::to_string(&lexeme);
+                while let Some(&next) = iterator.peek() {
+                    if !(next.is_ascii_alphanumeric() || next == '_') {
+                        break;
+                    }
+                    name.push(next);
+                    iterator.next();
+                }
+                if angle_suffix(&name, &mut tokens, &mut angle) {
+                    continue;
+                }
+                // A scale suffix multiplies the number before it.
+                let scaled = match (tokens.back(), scale(&name)) {
+                    (Some(&LexicalNumber(n, ref text)), Some(factor)) =>
+                        Some(match n.checked_mul(factor) {
+                            Some(n) => LexicalNumber(n, format!("{} {}", text, name)),
+                            None => LexicalOverflow(format!("{} {}", text, name), IntType::I32),
+                        }),
+                    (Some(&LexicalTyped(n, width, ref text)), Some(factor)) =>
+                        Some(match n.checked_mul(i64::from(factor)) {
+                            Some(n) => LexicalTyped(n, width, format!("{} {}", text, name)),
+                            None => LexicalOverflow(format!("{} {}", text, name), width),
+                        }),
+                    (Some(&LexicalFloat(FloatBits(x), ref text)), Some(factor)) =>
+                        Some(LexicalFloat(FloatBits(x * f64::from(factor)),
+                                          format!("{} {}", text, name))),
+                    _ => None,
+                };
+                if let Some(token) = scaled {
+                    tokens.pop_back();
+                    tokens.push_back(token);
+                    continue;
+                }
+                // So does a unit, which makes the number a quantity.
+                let quantity = match (tokens.back(), Units::from_name(&name)) {
+                    (Some(&LexicalNumber(n, ref text)), Some(units)) =>
+                        Some(LexicalQuantity(FloatBits(f64::from(n)), units,
+                                             format!("{} {}", text, name))),
+                    (Some(&LexicalFloat(FloatBits(x), ref text)), Some(units)) =>
+                        Some(LexicalQuantity(FloatBits(x), units, format!("{} {}", text, name))),
+                    _ => None,
+                };
+                if let Some(token) = quantity {
+                    tokens.pop_back();
+                    tokens.push_back(token);
+                    continue;
+                }
+                // `mod`, `MR`, `inf` and `nan` are reserved words, not
+                // identifiers.
+                match name.as_str() {
+                    "mod" => tokens.push_back(MODULO),
+                    "MR" => tokens.push_back(MEMORY),
+                    "inf" => tokens.push_back(LexicalFloat(FloatBits(f64::INFINITY), name)),
+                    "nan" => tokens.push_back(LexicalFloat(FloatBits(f64::NAN), name)),
+                    _ => tokens.push_back(Identifier(name)),
+                }
+            },
+            _ => if lexeme.is_whitespace() {
+                    continue;
+                 } else {
+                    tokens.push_back(UnknownToken(char::to_string(&lexeme)));
+                },
+        }
+    }
+    tokens.push_back(EOF);
+    tokens
+}
+
+
+/// The tokens of a line of input, taken one at a time, with `peek` to see
+/// the next token without taking it and `unread` to put one back.
+///
+/// The whole line is still lexed up front, since a number is not finished
+/// until the lexer has seen whether a scale suffix or angle follows it.
+pub struct TokenIter {
+    tokens: LinkedList<Token>,
+}
+
+impl TokenIter {
+    /// Lex `line` as `lex` does.
+    pub fn new(line: &str) -> TokenIter {
+        TokenIter { tokens: lex(line) }
+    }
+
+    /// The next token, which the next call to `next` will return.
+    pub fn peek(&self) -> Option<&Token> {
+        self.tokens.front()
+    }
+
+    /// Put `token` back, so that it is the next one returned.
+    pub fn unread(&mut self, token: Token) {
+        self.tokens.push_front(token);
+    }
+}
+
+impl Iterator for TokenIter {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Token> {
+        self.tokens.pop_front()
+    }
+}
+
+/// Write `tokens` back out as source text, separated by single spaces.
+/// Number literals keep the spelling they were lexed from, such as `007`,
+/// `1.50` or `5 k`, and `reprint(&lex(line))` reads back as the same
+/// tokens. Other tokens take their usual spelling, so `mod` becomes `%`, a
+/// superscript exponent becomes `^` and a `;` between arguments becomes `,`.
+pub fn reprint(tokens: &LinkedList<Token>) -> String {
+    use Token::*;
+    let words: Vec<String> = tokens.iter().filter_map(|token| match *token {
+        EOF => None,
+        LexicalNumber(_, ref text) | LexicalFloat(_, ref text) | LexicalTyped(_, _, ref text) |
+        LexicalQuantity(_, _, ref text) =>
+            Some(text.clone()),
+        ref token => Some(token.to_string()),
+    }).collect();
+    words.join(" ")
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn superscripts_lex_as_exponents() {
+        assert!(lex("5²") == lex("5 ^ 2"));
+        assert!(lex("2³") == lex("2 ^ 3"));
+        assert!(lex("2¹⁰") == lex("2 ^ 10"));
+        assert!(matches!(lex("²").pop_front(), Some(Token::UnknownToken(_))));
+    }
+
+    #[test]
+    fn eof_ends_every_input() {
+        assert!(lex("1 + 2").back() == Some(&Token::EOF));
+        assert!(lex("").into_iter().eq(vec![Token::EOF]));
+        assert_eq!(lex("1 + 2 3").iter().filter(|&token| *token == Token::EOF).count(), 1);
+    }
+
+    #[test]
+    fn minus_before_a_digit_in_value_position_is_a_literal() {
+        assert!(matches!(lex("-5 + 3").front(), Some(&Token::LexicalNumber(-5, _))));
+        let tokens: Vec<Token> = lex("3 - - 5").into_iter().collect();
+        assert!(tokens[1] == Token::MINUS);
+        assert!(tokens[2] == Token::NEGATE);
+        assert!(matches!(lex("3 - -5").iter().nth(2), Some(&Token::LexicalNumber(-5, _))));
+        assert!(lex("3 -5") == lex("3 - 5"));
+    }
+
+    #[test]
+    fn multi_character_operators_lex_as_one_token() {
+        for (input, token) in [("1 <= 2", Token::LE), ("1 >= 2", Token::GE),
+                               ("1 == 2", Token::EQ), ("1 != 2", Token::NE)] {
+            assert!(lex(input).iter().nth(1) == Some(&token), "{}", input);
+        }
+        assert!(lex("1 < 2").iter().nth(1) == Some(&Token::LT));
+        assert!(lex("1<=2") == lex("1 <= 2"));
+    }
+
+    #[test]
+    fn comma_decimal_separator() {
+        assert!(matches!(lex_with("2,75 + 1", ',').front(),
+                         Some(&Token::LexicalFloat(FloatBits(x), _)) if x == 2.75));
+        assert!(lex_with("max ( 1 ; 2 )", ',') == lex("max ( 1 , 2 )"));
+        assert!(matches!(lex("2,75").front(), Some(&Token::LexicalNumber(2, _))));
+    }
+
+    #[test]
+    fn tokens_display_as_written() {
+        use Token::*;
+        let s = String::from;
+        let cases = [
+            (UnknownToken(s("@")), "@"), (LexicalError(s("1.2.3")), "1.2.3"),
+            (LexicalNumber(42, s("042")), "42"), (LexicalFloat(FloatBits(1.5), s("1.50")), "1.5"),
+            (LexicalFloat(FloatBits(2.0), s("2")), "2.0"),
+            (LexicalFloat(FloatBits(f64::NAN), s("nan")), "nan"),
+            (LexicalTyped(-5, IntType::I8, s("-5i8")), "-5i8"),
+            (LexicalQuantity(FloatBits(5.0), Units([0, 1, 0]), s("5 m")), "5.0 m"),
+            (ResultRef(3), "#3"), (Identifier(s("x")), "x"),
+            (LexicalOverflow(s("99999999999"), IntType::I32), "99999999999"),
+            (LexicalOverflow(s("300"), IntType::I8), "300i8"),
+            (POW, "^"), (PLUS, "+"), (IDENTITY, "+"), (MINUS, "-"), (NEGATE, "-"), (TIMES, "*"),
+            (DIVIDE, "/"), (MODULO, "%"), (LPAREN, "("), (RPAREN, ")"), (COMMA, ","),
+            (ASSIGN, "="), (LT, "<"), (LE, "<="), (GT, ">"), (GE, ">="), (EQ, "=="), (NE, "!="),
+            (FACTORIAL, "!"), (MEMORY, "MR"), (EOF, "end of input"),
+        ];
+        for (token, expected) in cases {
+            assert_eq!(token.to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn reprint_keeps_the_spelling_of_literals() {
+        assert_eq!(reprint(&lex("007 +  1.50 * 5k")), "007 + 1.50 * 5 k");
+        assert_eq!(reprint(&lex("0x1.8p1 mod 2")), "0x1.8p1 % 2");
+        let line = "( 0x1.8p1 + 12 d 30 m ) ^ 2";
+        assert!(lex(&reprint(&lex(line))) == lex(line));
+    }
+
+    #[test]
+    fn peeking_does_not_advance() {
+        let mut tokens = TokenIter::new("1 + 2");
+        assert!(matches!(tokens.peek(), Some(&Token::LexicalNumber(1, _))));
+        assert!(matches!(tokens.peek(), Some(&Token::LexicalNumber(1, _))));
+        let first = tokens.next();
+        assert!(matches!(first, Some(Token::LexicalNumber(1, _))));
+        assert!(tokens.peek() == Some(&Token::PLUS));
+        tokens.unread(first.expect("there should be a first token"));
+        assert!(matches!(tokens.next(), Some(Token::LexicalNumber(1, _))));
+        assert!(tokens.next() == Some(Token::PLUS));
+        let rest: Vec<Token> = tokens.collect();
+        assert!(matches!(rest[..], [Token::LexicalNumber(2, _), Token::EOF]));
+    }
+
+    #[test]
+    fn hex_floats_lex_to_their_value() {
+        assert!(matches!(lex("0x1.8p1").pop_front(),
+                         Some(Token::LexicalFloat(FloatBits(x), ref text)) if x == 3.0 &&
+                                                                              text == "0x1.8p1"));
+        assert!(matches!(lex("0x.8p-1").pop_front(),
+                         Some(Token::LexicalFloat(FloatBits(x), _)) if x == 0.25));
+        assert!(matches!(lex("0x1.8p").pop_front(),
+                         Some(Token::LexicalError(ref text)) if text == "0x1.8p"));
+        assert!(matches!(lex("0x1g8p1").pop_front(),
+                         Some(Token::LexicalError(ref text)) if text == "0x1g8p1"));
+    }
+}
@@ -0,0 +1,34 @@
+//! A small calculator: a lexer, a shunting-yard parser and an evaluator
+//! for arithmetic expressions, plus the session state used by the REPL.
+
+#![allow(clippy::upper_case_acronyms, clippy::enum_variant_names)]
+
+#[macro_use]
+extern crate log;
+extern crate serde;
+extern crate toml;
+
+mod bytecode;
+mod calculator;
+mod config;
+mod eval;
+mod lexer;
+mod messages;
+mod parser;
+mod value;
+mod visitor;
+
+pub use bytecode::{compile, evaluate_vectorized, run, Program};
+pub use calculator::{eval_many, parse_repeat, Bits, CalcError, Calculator, Format, NanMode,
+                     Numbers, Quotient, Settings, Syntax, MAX_RECURSION_LIMIT};
+pub use config::{config_message, config_path, Config, ConfigError};
+pub use eval::{constant, equivalent, evaluate, evaluate_env, evaluate_with_timeout, sample, trace,
+               Env, EvalError, Warning};
+pub use lexer::{lex, lex_with, reprint, FloatBits, Token, TokenIter};
+pub use messages::{eval_message, eval_message_in, position_message_in, syntax_message,
+                   syntax_message_in, warning_message, warning_message_in, Language};
+pub use parser::{free_vars, operator_help, parse, parse_located, parse_rpn, parse_rpn_located,
+                 precedence_notes, redundant_parens, stats, to_grouped, to_sexpr, to_tree,
+                 Associativity, Comparison, Expr, ExprStats, Function, Statement, SyntaxError};
+pub use value::{to_words, IntType, Units, Value, MAX_PRECISION};
+pub use visitor::{dispatch, ExprVisitor};
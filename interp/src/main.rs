@@ -1,253 +1,675 @@
-use std::boxed::Box;
-use std::collections::HashMap;
-use std::collections::LinkedList;
+extern crate env_logger;
+extern crate interp;
+extern crate log;
+
+use std::env;
+use std::fs::{self, OpenOptions};
 use std::io::prelude::*;
-use std::io;
+use std::io::{self, IsTerminal};
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+use std::thread;
 
-#[derive(PartialEq, Eq, Hash)]
-enum Associativity { LEFT, RIGHT, }
+use interp::{config_message, config_path, eval_message, eval_message_in, evaluate, lex,
+             syntax_message, syntax_message_in, warning_message_in, Calculator, Config, Language,
+             Statement, Syntax, Token, Value};
 
-enum SyntaxError {
-    MalformedNumber(String),
-    UnknownSymbol(String),
-    MismatchedParentheses,
-    GeneralError,
+/// Print a message other than a result. In raw mode it goes to stderr, so
+/// that stdout holds only results.
+fn report(calculator: &Calculator, message: String) {
+    if calculator.settings.raw {
+        eprintln!("{}", message);
+    } else {
+        println!("{}", message);
+    }
 }
 
-#[derive(PartialEq, Eq, Hash)]
-enum Token {
-    UnknownToken(String), LexicalError(String), LexicalNumber(i32),
-    POW, PLUS, MINUS, TIMES, DIVIDE, MODULO, LPAREN, RPAREN,
+/// Whether to behave as an interactive REPL, with a banner and prompts.
+/// This is the default when stdin is a terminal, and `--interactive`
+/// forces it otherwise.
+fn is_interactive(args: &[String], stdin_is_terminal: bool) -> bool {
+    stdin_is_terminal || args.iter().any(|arg| arg == "--interactive")
 }
 
-enum Expr {
-    Number(i32),
-    Plus(Box<Expr>, Box<Expr>),
-    Minus(Box<Expr>, Box<Expr>),
-    Times(Box<Expr>, Box<Expr>),
-    Divide(Box<Expr>, Box<Expr>),
-    Modulo(Box<Expr>, Box<Expr>),
-    Pow(Box<Expr>, Box<Expr>),
+/// The prompt to print: `INTERP_PROMPT` if it is set, then the one in the
+/// configuration file, otherwise `>>> `.
+fn prompt(variable: Option<String>, config: Option<String>) -> String {
+    variable.or(config).unwrap_or_else(|| String::from(">>> "))
 }
 
+/// Whether `line` looks like an attempt to leave the REPL other than
+/// `quit` or `exit`.
+fn is_quit_typo(line: &str) -> bool {
+    matches!(line, "q" | ":q" | ":quit" | ":exit" | "quit()" | "exit()" | "quit;" | "exit;")
+}
 
-fn main() {
-    use SyntaxError::*;
-    let mut expression: Result<Expr, SyntaxError>;
-    let mut terminated = false;
-    println!("Calculator REPL. Type 'quit' or 'exit' to end session.");
-    while !terminated {
-        let mut line = String::new();
-        print!(">>> ");
-        io::stdout().flush().ok();
-        io::stdin().read_line(&mut line).ok().expect("Failed to read from STDIN.");
-        line = String::from(line.trim());
-        if line == "exit" || line == "quit" {
-            terminated = true;
-        } else if line == "" {
-            continue
-        } else {
-            expression = parse(lex(&line));
-            match expression {
-                Ok(expr) => match evaluate(&expr) {
-                                Ok(number) => println!("{}", number),
-                                Err(_) => println!("Cannot divide by zero!")
-                            },
-                Err(GeneralError) => println!("Syntax error."),
-                Err(MalformedNumber(number)) => println!("Cannot make a number from {}", number),
-                Err(MismatchedParentheses) => println!("Mismatched ( and )."),
-                Err(UnknownSymbol(symbol)) => println!("Unknown symbol: {}", symbol),
-           }
-       }
+/// Print and discard the warnings from the latest evaluation.
+fn show_warnings(calculator: &Calculator) {
+    let warnings: Vec<_> = calculator.warnings.borrow_mut().drain(..).collect();
+    for warning in warnings {
+        report(calculator, warning_message_in(warning, calculator.settings.language));
     }
 }
 
+/// Whether `input` has more `(` than `)`, so that an interactive user
+/// probably means to continue it on the next line.
+fn is_unbalanced(input: &str) -> bool {
+    let mut depth = 0;
+    for c in input.chars() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            _ => {},
+        }
+    }
+    depth > 0
+}
 
-fn lex(line: &String) -> LinkedList<Token> {
+/// Whether infix `input` ends with an operator which needs an operand
+/// after it, such as `1 +`. REPL commands never do.
+fn ends_with_operator(input: &str) -> bool {
     use Token::*;
-    let mut tokens: LinkedList<Token> = LinkedList::new();
-    let mut int_builder = String::from("");
-    let mut iterator = line.chars().peekable();
+    if input.starts_with(':') || input == "M+" || input == "M-" {
+        return false;
+    }
+    let mut tokens = lex(input);
+    tokens.pop_back();  // Remove the EOF.
+    matches!(tokens.back(),
+             Some(&PLUS) | Some(&MINUS) | Some(&TIMES) | Some(&DIVIDE) | Some(&MODULO) |
+             Some(&POW) | Some(&NEGATE) | Some(&IDENTITY) | Some(&COMMA) | Some(&ASSIGN) |
+             Some(&LT) | Some(&LE) | Some(&GT) | Some(&GE) | Some(&EQ) | Some(&NE))
+}
+
+/// Read a line from `input`, trimmed of surrounding whitespace. Returns
+/// `None` at the end of the input.
+fn read_line<R: BufRead>(input: &mut R) -> io::Result<Option<String>> {
+    let mut line = String::new();
+    if input.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    Ok(Some(String::from(line.trim())))
+}
+
+/// Read the file an editor saved for `:edit` as a single input, joining
+/// its lines with spaces and skipping blank ones, so that a long
+/// expression may be laid out over as many lines as it needs.
+fn read_edited(path: &Path) -> io::Result<String> {
+    let text = fs::read_to_string(path)?;
+    let lines: Vec<&str> = text.lines().map(str::trim).filter(|line| !line.is_empty()).collect();
+    Ok(lines.join(" "))
+}
+
+/// Create a new, empty file in `dir` for `:edit` to hand to the editor.
+/// The file must not exist already, so that a file or symbolic link left
+/// in a shared temporary directory is never written through. Each name
+/// which is taken is skipped for the next.
+fn create_edit_file(dir: &Path) -> io::Result<PathBuf> {
+    let mut attempt = 0;
     loop {
-        match iterator.next() {
-            Some(lexeme) => match lexeme {
-                '0' ... '9' => {
-                    int_builder.push(lexeme);
-                    if iterator.peek().is_none() ||
-                        !iterator.peek().unwrap().is_digit(10) {
-                        match int_builder.parse() {
-                            Ok(num) => tokens.push_back(LexicalNumber(num)),
-                            Err(_) => tokens.push_back(LexicalError(String::from(int_builder))),
-                        };
-                        int_builder = String::from("");
-                    }
-                },
-                '^' => tokens.push_back(POW),
-                '+' => tokens.push_back(PLUS),
-                '-' => if iterator.peek().unwrap().is_digit(10) {
-                        int_builder.push(lexeme);
-                        } else {
-                            tokens.push_back(MINUS);
-                        },
-                '*' => tokens.push_back(TIMES),
-                '/' => tokens.push_back(DIVIDE),
-                '%' => tokens.push_back(MODULO),
-                '(' => tokens.push_back(LPAREN),
-                ')' => tokens.push_back(RPAREN),
-                _ => if lexeme.is_whitespace() {
-                        continue;
-                     } else {
-                        tokens.push_back(UnknownToken(char::to_string(&lexeme)));
-                    },
-            },
-            None => break,
+        let path = dir.join(format!("interp-edit-{}-{}.txt", process::id(), attempt));
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+        match options.open(&path) {
+            Ok(_) => return Ok(path),
+            Err(ref error) if error.kind() == io::ErrorKind::AlreadyExists && attempt < 100 =>
+                attempt += 1,
+            Err(error) => return Err(error),
         }
     }
-    tokens
 }
 
+/// Compose an input for `:edit` in `editor`, the value of `$EDITOR`, which
+/// may include arguments, by having it edit a temporary file.
+fn edit_in(editor: &str) -> Result<String, String> {
+    let dir = env::temp_dir();
+    let path = create_edit_file(&dir).map_err(|error| {
+        format!("Could not create a file in {}: {}", dir.display(), error)
+    })?;
+    let mut words = editor.split_whitespace();
+    let program = words.next().unwrap_or(editor);
+    let result = match Command::new(program).args(words).arg(&path).status() {
+        Ok(status) if status.success() => read_edited(&path)
+            .map_err(|error| format!("Could not read {}: {}", path.display(), error)),
+        Ok(status) => Err(format!("{} failed: {}", editor, status)),
+        Err(error) => Err(format!("Could not run {}: {}", editor, error)),
+    };
+    fs::remove_file(&path).ok();
+    result
+}
 
-fn parse(tokens: LinkedList<Token>) -> Result<Expr, SyntaxError> {
-    use Associativity::*;
-    use Expr::*;
-    use SyntaxError::*;
-    use Token::*;
-    // Operator-precedence table.
-    let mut op_table : HashMap<Token, (u32, Associativity)> = HashMap::new();
-    op_table.insert(POW,    (4, RIGHT));
-    op_table.insert(TIMES,  (3, LEFT));
-    op_table.insert(DIVIDE, (3, LEFT));
-    op_table.insert(PLUS,   (2, LEFT));
-    op_table.insert(MINUS,  (2, LEFT));
-    op_table.insert(MODULO, (1, LEFT));
-    op_table.insert(LPAREN, (9, LEFT));
-    op_table.insert(RPAREN, (0, LEFT));
-    // Dijkstra's shunting-yard algorithm.
-    let mut operator_stack: Vec<Token> = Vec::new();
-    let mut operand_queue: LinkedList<Expr> = LinkedList::new();
-    for token in tokens {
-        match token {
-            UnknownToken(error) => return Err(UnknownSymbol(error.clone())),
-            LexicalError(error) => return Err(MalformedNumber(error.clone())),
-            LexicalNumber(number) => operand_queue.push_front(Number(number)),
-            LPAREN => operator_stack.push(LPAREN),
-            RPAREN => {
-                while *operator_stack.last().unwrap() != LPAREN {
-                    if operator_stack.len() == 0 {
-                        return Err(MismatchedParentheses);
-                    }
-                    let l_op = operand_queue.pop_front().unwrap();
-                    let r_op = operand_queue.pop_front().unwrap();
-                    match construct_expr(operator_stack.pop(), l_op, r_op) {
-                        Ok(expr) => operand_queue.push_back(expr),
-                        Err(error) => return Err(error),
-                    };
-                };
-                if operator_stack.len() == 0 {
-                    return Err(MismatchedParentheses);
-                }
-                operator_stack.pop();  // Remove matching LPAREN.
-            },
-            operator =>
-                if operator_stack.len() == 0 ||
-                   *operator_stack.last().unwrap() == LPAREN {
-                      operator_stack.push(operator);
-                } else {
-                    loop {
-                        let op2 = operator_stack.pop().unwrap();
-                        let (ref p1, ref a1) = *(op_table.get(&operator).unwrap());
-                        let (ref p2, _) = *(op_table.get(&op2).unwrap());
-                        if (p1 < p2 && *a1 == RIGHT) || (p1 <= p2 && *a1 == LEFT) {
-                            let l_op = operand_queue.pop_front().unwrap();
-                            let r_op = operand_queue.pop_front().unwrap();
-                            let op2_pop = operator_stack.pop();
-                            match construct_expr(op2_pop, l_op, r_op) {
-                                Ok(expr) => operand_queue.push_back(expr),
-                                Err(error) => return Err(error),
-                            }
-                        } else {
-                            operator_stack.push(op2);
-                            break;
-                        }
-                    }
-                    operator_stack.push(operator);
-                },
+/// Compose an input for `:edit` without an editor, by reading lines up to
+/// an empty one or the end of the input and joining them with spaces.
+fn edit_inline<R: BufRead>(input: &mut R, interactive: bool) -> Result<String, String> {
+    let mut lines = Vec::new();
+    loop {
+        if interactive {
+            print!("... ");
+            io::stdout().flush().ok();
+        }
+        match read_line(input) {
+            Ok(Some(line)) if !line.is_empty() => lines.push(line),
+            Ok(_) => return Ok(lines.join(" ")),
+            Err(error) => return Err(format!("Could not read input: {}", error)),
         }
     }
-    // All tokens have been consumed from user input.
-    while operator_stack.len() > 0 {
-        match operator_stack.pop() {
-            None => return Err(GeneralError),
-            Some(LPAREN) => return Err(MismatchedParentheses),
-            Some(RPAREN) => return Err(MismatchedParentheses),
-            Some(LexicalError(error)) => return Err(UnknownSymbol(error.clone())),
-            operator => {
-                if operand_queue.len() < 2 {
-                    return Err(GeneralError);
-                } else {
-                    let l_op = operand_queue.pop_front().unwrap();
-                    let r_op = operand_queue.pop_front().unwrap();
-                    match construct_expr(operator, l_op, r_op) {
-                        Ok(expr) => operand_queue.push_back(expr),
-                        Err(error) => return Err(error),
-                    };
-                }
+}
+
+/// Whether it is worth reading more input after `error`: the line was
+/// not UTF-8, or the read was interrupted, but the input is still there.
+fn is_recoverable(error: &io::Error) -> bool {
+    matches!(error.kind(), io::ErrorKind::InvalidData | io::ErrorKind::Interrupted)
+}
+
+/// The name and version of the crate, printed by `--version`.
+fn version() -> String {
+    format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"))
+}
+
+/// Inputs run by `--self-test`, each with the output expected from a new
+/// session.
+const SELF_TEST: &[(&str, &str)] = &[
+    ("1 + 2 * 3", "7"),
+    ("( 1 + 2 ) * 3", "9"),
+    ("10 - 4 - 3", "3"),
+    ("100 / 10 / 5", "2"),
+    ("1 - 2 - 3 - 4", "-8"),
+    ("100 - 50 - 25 - 12 - 6", "7"),
+    ("1 - 2 + 3 - 4 + 5", "3"),
+    ("64 / 4 / 2 / 2", "4"),
+    ("360 / 6 / 3 * 2 / 4", "10"),
+    ("100 % 30 % 7 % 4", "3"),
+    ("2 ^ 3 ^ 2", "512"),
+    ("-2 ^ 2", "4"),
+    ("- 2 ^ 2", "-4"),
+    ("6 + 4 % 3", "7"),
+    ("5!", "120"),
+    ("sum ( 1 , 2 , 3 )", "6"),
+    ("abs ( min ( - 3 , 2 ) )", "3"),
+    ("1 + 2 < 4", "true"),
+    ("5 k + 500", "5500"),
+    ("7 / 0", "Cannot divide by zero!"),
+    ("2147483647 + 1", "Overflow: result does not fit in i32."),
+];
+
+/// Run each of `SELF_TEST` through the whole pipeline and report any
+/// which fail. Returns whether all of them passed.
+fn self_test() -> bool {
+    let mut failed = 0;
+    for &(input, expected) in SELF_TEST {
+        let calculator = Calculator::new();
+        let output = match calculator.parse(input) {
+            Ok(expr) => match evaluate(&expr, &calculator) {
+                Ok(value) => calculator.display(value),
+                Err(error) => eval_message(error),
             },
+            Err(error) => syntax_message(error),
         };
-    };
-    if operand_queue.len() != 1 {
-        return Err(GeneralError);
+        if output != expected {
+            println!("FAIL: {} gave {}, expected {}", input, output, expected);
+            failed += 1;
+        }
     }
-    Ok(operand_queue.pop_front().unwrap())
+    println!("{} passed, {} failed.", SELF_TEST.len() - failed, failed);
+    failed == 0
 }
 
+/// Run each non-blank line of `text` in turn, for `--file`. Returns
+/// whether all of them succeeded.
+fn run_file(calculator: &mut Calculator, text: &str) -> bool {
+    let mut succeeded = true;
+    for line in text.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        succeeded &= execute(calculator, line);
+    }
+    succeeded
+}
 
-fn construct_expr(token: Option<Token>, l_op: Expr, r_op: Expr) -> Result<Expr, SyntaxError> {
-    use Expr::*;
-    use SyntaxError::*;
-    use Token::*;
-    let expr : Expr;
-    match token {
-        Some(POW) => expr = Pow(Box::new(l_op), Box::new(r_op)),
-        Some(DIVIDE) => expr = Divide(Box::new(l_op), Box::new(r_op)),
-        Some(TIMES) => expr = Times(Box::new(l_op), Box::new(r_op)),
-        Some(PLUS) => expr = Plus(Box::new(l_op), Box::new(r_op)),
-        Some(MINUS) => expr = Minus(Box::new(l_op), Box::new(r_op)),
-        Some(MODULO) => expr = Modulo(Box::new(l_op), Box::new(r_op)),
-        Some(LexicalError(error)) => return Err(UnknownSymbol(error.clone())),
-        _ => return  Err(GeneralError),
-    };
-    Ok(expr)
-}
-
-
-fn evaluate(expr: &Expr) -> Result<i32, String> {
-    use Expr::*;
-    match expr {
-        &Number(n) => Ok(n),
-        &Pow(ref e_left, ref e_right) =>
-            Ok(evaluate(e_left).unwrap().pow(evaluate(e_right).unwrap() as u32)),
-        &Plus(ref e_left, ref e_right) =>
-            Ok(evaluate(e_left).unwrap() + evaluate(e_right).unwrap()),
-        &Minus(ref e_left, ref e_right) =>
-            Ok(evaluate(e_left).unwrap() - evaluate(e_right).unwrap()),
-        &Times(ref e_left, ref e_right) =>
-            Ok(evaluate(e_left).unwrap() * evaluate(e_right).unwrap()),
-        &Divide(ref e_left, ref e_right) => {
-            let result: i32 = evaluate(e_right).unwrap();
-            if result == 0 {
-                return Err(String::from("Division by zero!"));
-            } else {
-                return Ok(evaluate(e_left).unwrap() / result);
+/// Run one line of input and add it to the transcript if it succeeded.
+/// Returns whether it did.
+fn execute(calculator: &mut Calculator, line: &str) -> bool {
+    let succeeded = run_line(calculator, line);
+    // `:export` writes out the transcript, so replaying it need not.
+    if succeeded && !line.starts_with(":export") {
+        calculator.transcript.push(String::from(line));
+    }
+    succeeded
+}
+
+/// Run one line of input: a memory or `:` command, a definition, an
+/// assignment or an expression. Returns whether it succeeded.
+fn run_line(calculator: &mut Calculator, line: &str) -> bool {
+    if let Some(message) = calculator.memory_command(line) {
+        report(calculator, message);
+        return true;
+    }
+    if line.starts_with(':') {
+        let message = calculator.command(line);
+        report(calculator, message);
+        return true;
+    }
+    match calculator.parse_statement(line) {
+        Ok(Statement::Expression(expr)) => {
+            let result = evaluate(&expr, calculator);
+            show_warnings(calculator);
+            match result {
+                Ok(value) => {
+                    calculator.record(line, value);
+                    println!("{}", calculator.display(value));
+                    true
+                },
+                Err(error) => {
+                    report(calculator, eval_message_in(error, calculator.settings.language));
+                    false
+                },
             }
         },
-        &Modulo(ref e_left, ref e_right) => {
-            let result: i32 = evaluate(e_right).unwrap();
-            if result == 0 {
-                return Err(String::from("Division by zero!"));
-            } else {
-                return Ok(evaluate(e_left).unwrap() % result);
+        Ok(Statement::Definition(name, function)) => {
+            report(calculator, format!("Defined {}({}).", name, function.params.join(", ")));
+            calculator.functions.insert(name, function);
+            true
+        },
+        Ok(Statement::Assignment(names, expr)) => {
+            let result = evaluate(&expr, calculator).and_then(|value| {
+                calculator.assign(&names, value).map(|_| value)
+            });
+            show_warnings(calculator);
+            match result {
+                Ok(value) => {
+                    let message = format!("{} = {}", names.join(" = "), calculator.display(value));
+                    report(calculator, message);
+                    true
+                },
+                Err(error) => {
+                    report(calculator, eval_message_in(error, calculator.settings.language));
+                    false
+                },
             }
         },
+        Err(error) => {
+            report(calculator, syntax_message_in(error, calculator.settings.language));
+            false
+        },
+    }
+}
+
+/// Evaluate `input` for `--test`, returning the exit status: 0 if it is
+/// true, 1 if it is false and 2 if it is not a boolean or fails.
+fn test(calculator: &Calculator, input: &str) -> i32 {
+    let language = calculator.settings.language;
+    let result = match calculator.parse(input) {
+        Ok(expr) => evaluate(&expr, calculator).map_err(|error| eval_message_in(error, language)),
+        Err(error) => Err(syntax_message_in(error, language)),
+    };
+    show_warnings(calculator);
+    match result {
+        Ok(Value::Bool(true)) => 0,
+        Ok(Value::Bool(false)) => 1,
+        Ok(value) => {
+            eprintln!("--test needs a comparison, such as 3 > 2, not {}.",
+                      calculator.display(value));
+            2
+        },
+        Err(message) => {
+            eprintln!("{}", message);
+            2
+        },
+    }
+}
+
+/// The stack the session runs with. Evaluation recurses through each level
+/// of an expression, so this leaves room for `MAX_RECURSION_LIMIT` calls of
+/// a function whose body nests a hundred levels deep.
+const SESSION_STACK_SIZE: usize = 512 * 1024 * 1024;
+
+
+fn main() {
+    let session = thread::Builder::new()
+        .stack_size(SESSION_STACK_SIZE)
+        .spawn(session)
+        .expect("Failed to start the session.");
+    if session.join().is_err() {
+        process::exit(101);
+    }
+}
+
+
+fn session() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == "--version") {
+        println!("{}", version());
+        return;
+    }
+    if args.iter().any(|arg| arg == "--self-test") {
+        process::exit(if self_test() { 0 } else { 1 });
+    }
+    // --verbose logs each step of parsing and evaluation to stderr.
+    if args.iter().any(|arg| arg == "--verbose") {
+        env_logger::Builder::new().filter_level(log::LevelFilter::Debug).init();
+    }
+    let config = match config_path().map(|path| Config::load(&path)) {
+        Some(Ok(config)) => config,
+        Some(Err(error)) => {
+            eprintln!("{}", config_message(error));
+            Config::default()
+        },
+        None => Config::default(),
+    };
+    let interactive = is_interactive(&args, io::stdin().is_terminal());
+    let prompt = prompt(env::var("INTERP_PROMPT").ok(), config.prompt);
+    let mut calculator = Calculator::new();
+    calculator.settings = config.settings;
+    // INTERP_LANG chooses the language of messages over the configuration.
+    if let Ok(code) = env::var("INTERP_LANG") {
+        match Language::from_code(&code) {
+            Some(language) => calculator.settings.language = language,
+            None => eprintln!("Unknown language in INTERP_LANG: {}, expected en or es.", code),
+        }
+    }
+    calculator.startup = calculator.settings.clone();
+    if let Some(position) = args.iter().position(|arg| arg == "--test") {
+        let status = match args.get(position + 1) {
+            Some(input) => test(&calculator, input),
+            None => {
+                eprintln!("Usage: --test EXPR");
+                2
+            },
+        };
+        process::exit(status);
+    }
+    // --file runs each line of a file, such as one written by :export, in
+    // one session.
+    if let Some(position) = args.iter().position(|arg| arg == "--file") {
+        let path = match args.get(position + 1) {
+            Some(path) => path,
+            None => {
+                eprintln!("Usage: --file PATH");
+                process::exit(1);
+            },
+        };
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(error) => {
+                eprintln!("Could not read {}: {}", path, error);
+                process::exit(1);
+            },
+        };
+        process::exit(if run_file(&mut calculator, &text) { 0 } else { 1 });
+    }
+    // Any other arguments are inputs to evaluate in turn, in one session,
+    // instead of reading them from stdin.
+    let inputs: Vec<&String> = args.iter().filter(|arg| !arg.starts_with("--")).collect();
+    if !inputs.is_empty() {
+        let mut status = 0;
+        for input in inputs {
+            if !execute(&mut calculator, input.trim()) {
+                status = 1;
+            }
+        }
+        process::exit(status);
+    }
+    // --stdin-once evaluates the first line of stdin and ignores the rest.
+    if args.iter().any(|arg| arg == "--stdin-once") {
+        let status = match read_line(&mut io::stdin().lock()) {
+            Ok(Some(line)) => if execute(&mut calculator, &line) { 0 } else { 1 },
+            Ok(None) => {
+                eprintln!("--stdin-once needs a line of input.");
+                1
+            },
+            Err(error) => {
+                eprintln!("Could not read input: {}", error);
+                1
+            },
+        };
+        process::exit(status);
+    }
+    let mut terminated = false;
+    if interactive {
+        println!("Calculator REPL. Type 'quit' or 'exit' to end session.");
+    }
+    // Stdout is line-buffered even when it is a pipe, so each result
+    // reaches a pager or `head` as soon as its line is printed; only the
+    // prompts, which end without a newline, need an explicit flush.
+    let stdin = io::stdin();
+    let mut input = stdin.lock();
+    while !terminated {
+        if interactive && !calculator.settings.raw {
+            print!("{}", prompt);
+            io::stdout().flush().ok();
+        }
+        let mut line = match read_line(&mut input) {
+            Ok(Some(line)) => line,
+            Ok(None) => {
+                if interactive {
+                    println!();
+                }
+                break;
+            },
+            Err(error) => {
+                eprintln!("Could not read input: {}", error);
+                if is_recoverable(&error) {
+                    continue;
+                }
+                process::exit(1);
+            },
+        };
+        // Keep reading until the parentheses balance and the input does not
+        // end with an operator. An empty line cancels the whole input, and
+        // the end of the input leaves it as it is.
+        while interactive && (is_unbalanced(&line) ||
+                              (calculator.settings.syntax == Syntax::Infix &&
+                               ends_with_operator(&line))) {
+            print!("... ");
+            io::stdout().flush().ok();
+            match read_line(&mut input) {
+                Ok(Some(more)) if more.is_empty() => {
+                    line.clear();
+                    break;
+                },
+                Ok(Some(more)) => {
+                    line.push(' ');
+                    line.push_str(&more);
+                },
+                Ok(None) => break,
+                Err(error) => {
+                    eprintln!("Could not read input: {}", error);
+                    line.clear();
+                    break;
+                },
+            }
+        }
+        if line == "exit" || line == "quit" {
+            terminated = true;
+        } else if line.is_empty() {
+            continue
+        } else if is_quit_typo(&line) {
+            report(&calculator, String::from("Type 'quit' or 'exit' to leave."));
+        } else if line == ":edit" {
+            // Without $EDITOR, fall back to continuation lines.
+            let edited = match env::var("EDITOR").ok().filter(|editor| !editor.trim().is_empty()) {
+                Some(editor) => edit_in(&editor),
+                None => {
+                    report(&calculator, String::from("EDITOR is not set: type the input, \
+                                                      then an empty line to run it."));
+                    edit_inline(&mut input, interactive)
+                },
+            };
+            match edited {
+                Ok(edited) if edited.is_empty() => {},
+                Ok(edited) => {
+                    execute(&mut calculator, &edited);
+                },
+                Err(message) => eprintln!("{}", message),
+            }
+        } else {
+            execute(&mut calculator, &line);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A new, empty directory for a test to create files in.
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("interp-test-{}-{}", name, process::id()));
+        fs::remove_dir_all(&dir).ok();
+        fs::create_dir(&dir).expect("the directory should be created");
+        dir
+    }
+
+    #[test]
+    fn edit_file_skips_names_which_are_taken() {
+        let dir = scratch_dir("edit-file");
+        let taken = dir.join(format!("interp-edit-{}-0.txt", process::id()));
+        fs::write(&taken, "1 + 1").unwrap();
+        let path = create_edit_file(&dir).expect("a file should be created");
+        assert!(path != taken);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "");
+        assert_eq!(fs::read_to_string(&taken).unwrap(), "1 + 1");
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn edit_file_does_not_follow_a_symbolic_link() {
+        let dir = scratch_dir("edit-link");
+        let target = dir.join("target.txt");
+        fs::write(&target, "keep").unwrap();
+        std::os::unix::fs::symlink(&target,
+                                   dir.join(format!("interp-edit-{}-0.txt", process::id())))
+            .unwrap();
+        create_edit_file(&dir).expect("a file should be created");
+        assert_eq!(fs::read_to_string(&target).unwrap(), "keep");
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn edited_lines_are_joined_without_blank_ones() {
+        let dir = scratch_dir("read-edited");
+        let path = dir.join("input.txt");
+        fs::write(&path, "  1 +\n\n   2 *\n3  \n\n").unwrap();
+        assert_eq!(read_edited(&path).unwrap(), "1 + 2 * 3");
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn interactive_flag_forces_the_repl() {
+        let forced = vec![String::from("--interactive")];
+        assert!(is_interactive(&forced, false));
+        assert!(!is_interactive(&[], false));
+        assert!(is_interactive(&[], true));
+        assert!(!is_interactive(&[String::from("1 + 2")], false));
+    }
+
+    #[test]
+    fn prompt_prefers_the_environment_then_the_configuration() {
+        assert_eq!(prompt(Some(String::from("$ ")), Some(String::from("> "))), "$ ");
+        assert_eq!(prompt(None, Some(String::from("> "))), "> ");
+        assert_eq!(prompt(None, None), ">>> ");
+    }
+
+    #[test]
+    fn quit_typos_are_recognised() {
+        for typo in ["q", ":q", ":quit", "exit()", "quit;"] {
+            assert!(is_quit_typo(typo), "{}", typo);
+        }
+        for line in ["quit", "exit", "quote", "q + 1", ":mode"] {
+            assert!(!is_quit_typo(line), "{}", line);
+        }
+    }
+
+    #[test]
+    fn version_names_the_package() {
+        assert_eq!(version(), format!("interp {}", env!("CARGO_PKG_VERSION")));
+    }
+
+    #[test]
+    fn unbalanced_parentheses_need_more_input() {
+        assert!(!is_unbalanced("( 1 + 2 ) * 3"));
+        assert!(!is_unbalanced("1 + 2"));
+        assert!(is_unbalanced("( 1 + ( 2"));
+        assert!(is_unbalanced("max ( 1 ,"));
+        // A stray `)` cannot be fixed by reading more, so it is left to the parser.
+        assert!(!is_unbalanced("1 + 2 )"));
+    }
+
+    #[test]
+    fn trailing_operators_need_more_input() {
+        assert!(ends_with_operator("1 +"));
+        assert!(ends_with_operator("2 * ( 3 -"));
+        assert!(ends_with_operator("1 <="));
+        assert!(!ends_with_operator("1 + 2"));
+        assert!(!ends_with_operator("3 !"));
+        // Unbalanced, but not ending with an operator.
+        assert!(!ends_with_operator("( 1 + 2"));
+        assert!(is_unbalanced("( 1 + 2"));
+    }
+
+    #[test]
+    fn built_in_cases_pass() {
+        assert!(self_test());
+    }
+
+    #[test]
+    fn test_gives_the_exit_status_of_a_comparison() {
+        let calculator = Calculator::new();
+        assert_eq!(test(&calculator, "3 > 2"), 0);
+        assert_eq!(test(&calculator, "3 < 2"), 1);
+        assert_eq!(test(&calculator, "3 + 2"), 2);
+        assert_eq!(test(&calculator, "1 / 0 > 2"), 2);
+    }
+
+    #[test]
+    fn later_lines_of_a_file_may_use_ans() {
+        let mut calculator = Calculator::new();
+        assert!(run_file(&mut calculator, "6 * 7\n\nans + 1\n"));
+        assert!(matches!(calculator.results.last(), Some(&Value::Int(43))));
+        assert!(!run_file(&mut calculator, "1 / 0\nans\n"));
+    }
+
+    #[test]
+    fn read_line_returns_errors_instead_of_panicking() {
+        let mut input = io::Cursor::new(&b"  1 + 2 \n\xff\xfe\n"[..]);
+        assert_eq!(read_line(&mut input).ok(), Some(Some(String::from("1 + 2"))));
+        assert!(matches!(read_line(&mut input), Err(ref error)
+                         if error.kind() == io::ErrorKind::InvalidData));
+        let mut empty = io::Cursor::new(&b""[..]);
+        assert_eq!(read_line(&mut empty).ok(), Some(None));
+    }
+
+    #[test]
+    fn an_exported_session_replays_to_the_same_variables() {
+        let dir = scratch_dir("export");
+        let script = dir.join("session.txt");
+        let mut calculator = Calculator::new();
+        for line in ["x = 6", "sq ( n ) = n * n", "1 / 0", "y = sq ( x ) + 1", ":numbers float",
+                     "z = y / 2", "x = x + 1"] {
+            execute(&mut calculator, line);
+        }
+        execute(&mut calculator, &format!(":export {}", script.display()));
+        let text = fs::read_to_string(&script).expect("the session should be exported");
+        assert!(!text.contains("1 / 0"));
+        let mut replayed = Calculator::new();
+        assert!(run_file(&mut replayed, &text));
+        let shown = |calculator: &Calculator| {
+            let mut vars: Vec<String> = calculator.vars.iter()
+                .map(|(name, &value)| format!("{} = {}", name, calculator.display(value)))
+                .collect();
+            vars.sort();
+            vars
+        };
+        assert_eq!(shown(&replayed), vec!["x = 7", "y = 37", "z = 18.5"]);
+        assert_eq!(shown(&replayed), shown(&calculator));
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_rejects_a_misplaced_assignment() {
+        assert_eq!(test(&Calculator::new(), "1 + x = 2"), 2);
     }
 }
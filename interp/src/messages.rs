@@ -0,0 +1,291 @@
+use std::fmt;
+
+use serde::Deserialize;
+
+use eval::{EvalError, Warning};
+use lexer::Token;
+use parser::SyntaxError;
+use value::Value;
+
+/// The language of error and warning messages, set with `:lang` or the
+/// `INTERP_LANG` environment variable.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub enum Language {
+    #[default]
+    #[serde(rename = "en")]
+    English,
+    #[serde(rename = "es")]
+    Spanish,
+}
+
+impl Language {
+    /// The language with the code `code`, such as `es`. A locale name such
+    /// as `es_ES.UTF-8` also names its language.
+    pub fn from_code(code: &str) -> Option<Language> {
+        match code.split(['_', '.']).next() {
+            Some("en") => Some(Language::English),
+            Some("es") => Some(Language::Spanish),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Language {
+    /// The code of the language, as given to `:lang`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Language::English => write!(f, "en"),
+            Language::Spanish => write!(f, "es"),
+        }
+    }
+}
+
+
+/// The message printed for a syntax error, in `language`.
+pub fn syntax_message_in(error: SyntaxError, language: Language) -> String {
+    match language {
+        Language::English => syntax_message(error),
+        Language::Spanish => spanish_syntax_message(error),
+    }
+}
+
+
+/// Where in the input a syntax error was found, in `language`: at the
+/// token with index `index`, which is `token`. The tokens are numbered from
+/// 1 for people to read.
+pub fn position_message_in(index: usize, token: Option<Token>, language: Language) -> String {
+    match (token, language) {
+        (None, Language::English) | (Some(Token::EOF), Language::English) =>
+            String::from("At the end of the input."),
+        (None, Language::Spanish) | (Some(Token::EOF), Language::Spanish) =>
+            String::from("Al final de la entrada."),
+        (Some(token), Language::English) => format!("At token {}: {}", index + 1, token),
+        (Some(token), Language::Spanish) => format!("En el símbolo {}: {}", index + 1, token),
+    }
+}
+
+
+/// The message printed for a warning from evaluation, in `language`.
+pub fn warning_message_in(warning: Warning, language: Language) -> String {
+    match language {
+        Language::English => warning_message(warning),
+        Language::Spanish => spanish_warning_message(warning),
+    }
+}
+
+
+/// The message printed for an error during evaluation, in `language`.
+pub fn eval_message_in(error: EvalError, language: Language) -> String {
+    match language {
+        Language::English => eval_message(error),
+        Language::Spanish => spanish_eval_message(error),
+    }
+}
+
+
+/// The message printed for a syntax error.
+pub fn syntax_message(error: SyntaxError) -> String {
+    use SyntaxError::*;
+    match error {
+        GeneralError => String::from("Syntax error."),
+        MalformedNumber(number) => format!("Cannot make a number from {}", number),
+        NumberTooLarge(ref number, width) if number.len() > 20 =>
+            format!("Number too large for {}: {}... ({} digits)",
+                    width, &number[..10], number.trim_start_matches('-').len()),
+        NumberTooLarge(number, width) => format!("Number too large for {}: {}", width, number),
+        MismatchedParentheses => String::from("Mismatched ( and )."),
+        MissingOperand => String::from("An operator is missing an operand."),
+        TooManyOperands => String::from("Too many operands: is an operator missing?"),
+        UnexpectedEnd => String::from("Input ended unexpectedly."),
+        OperatorDisabled(symbol) => format!("The {} operator is switched off.", symbol),
+        UnknownSymbol(symbol) => format!("Unknown symbol: {}", symbol),
+    }
+}
+
+
+/// The message printed for a warning from evaluation.
+pub fn warning_message(warning: Warning) -> String {
+    match warning {
+        Warning::UnknownVariable(name) =>
+            format!("Warning: {} is not defined, using 0.", name),
+        Warning::ShadowsConstant(name) =>
+            format!("Warning: {} now hides the constant of the same name.", name),
+        Warning::PrecisionInIntegerMode =>
+            String::from("Warning: precision has no effect in integer mode, except on \
+                          quantities."),
+    }
+}
+
+
+/// The message printed for an error during evaluation.
+pub fn eval_message(error: EvalError) -> String {
+    use EvalError::*;
+    match error {
+        DivisionByZero => String::from("Cannot divide by zero!"),
+        NoSuchResult(index, count) =>
+            format!("There is no result #{} ({} results so far).", index, count),
+        NotFinite(value) =>
+            format!("Result is not a finite number: {}", Value::Float(value)),
+        FloatInIntegerMode(value) =>
+            format!("Cannot use {} in integer mode, try :numbers float.", Value::Float(value)),
+        UnknownFunction(name) => format!("Unknown function: {}", name),
+        NoArguments(name) => format!("{} needs at least one argument.", name),
+        ArgumentCount(name, 1) => format!("{} takes 1 argument.", name),
+        ArgumentCount(name, count) => format!("{} takes {} arguments.", name, count),
+        EmptyRange(lo, hi) =>
+            format!("Lower bound {} is greater than upper bound {}.", lo, hi),
+        UnknownVariable(name) => format!("Unknown variable: {}", name),
+        RecursionLimitExceeded(limit) =>
+            format!("Function calls nested more than {} deep.", limit),
+        Overflow(width) => format!("Overflow: result does not fit in {}.", width),
+        MismatchedWidths(l, r) => format!("Cannot combine {} with {}.", l, r),
+        FactorialDomain(value) =>
+            format!("Factorial is only defined for non-negative integers, not {}.", value),
+        FactorialOverflow(n, largest, width) =>
+            format!("Factorial of {} exceeds representable range: the largest that fits in {} is {}!",
+                    n, width, largest),
+        NotANumber(value) => format!("Expected a number, not {}.", value),
+        NotCompilable => String::from("Only integer arithmetic can be compiled."),
+        NegativeExponent =>
+            String::from("Cannot raise an integer to a negative power, try :numbers float."),
+        Timeout(timeout) => format!("Evaluation took longer than {:?}.", timeout),
+        ConstantAssignment(name) => format!("Cannot assign to the constant {}.", name),
+        NoPreviousResult => String::from("There is no previous result for ans to refer to."),
+        NoInverse(divisor, modulus) =>
+            format!("Cannot divide by {}, which has no inverse modulo {}.", divisor, modulus),
+        IncompatibleUnits(l, r) => format!("Cannot combine units {} and {}.", l, r),
+        UnitExponent =>
+            String::from("Only an integer without units can be the power of a quantity."),
+    }
+}
+
+
+fn spanish_syntax_message(error: SyntaxError) -> String {
+    use SyntaxError::*;
+    match error {
+        GeneralError => String::from("Error de sintaxis."),
+        MalformedNumber(number) => format!("No se puede formar un número con {}", number),
+        NumberTooLarge(ref number, width) if number.len() > 20 =>
+            format!("Número demasiado grande para {}: {}... ({} dígitos)",
+                    width, &number[..10], number.trim_start_matches('-').len()),
+        NumberTooLarge(number, width) =>
+            format!("Número demasiado grande para {}: {}", width, number),
+        MismatchedParentheses => String::from("Los ( y ) no coinciden."),
+        MissingOperand => String::from("A un operador le falta un operando."),
+        TooManyOperands => String::from("Demasiados operandos: ¿falta un operador?"),
+        UnexpectedEnd => String::from("La entrada terminó inesperadamente."),
+        OperatorDisabled(symbol) => format!("El operador {} está desactivado.", symbol),
+        UnknownSymbol(symbol) => format!("Símbolo desconocido: {}", symbol),
+    }
+}
+
+
+fn spanish_warning_message(warning: Warning) -> String {
+    match warning {
+        Warning::UnknownVariable(name) =>
+            format!("Aviso: {} no está definida, se usa 0.", name),
+        Warning::ShadowsConstant(name) =>
+            format!("Aviso: {} oculta ahora la constante del mismo nombre.", name),
+        Warning::PrecisionInIntegerMode =>
+            String::from("Aviso: la precisión no tiene efecto en el modo entero, salvo en \
+                          las magnitudes."),
+    }
+}
+
+
+fn spanish_eval_message(error: EvalError) -> String {
+    use EvalError::*;
+    match error {
+        DivisionByZero => String::from("¡No se puede dividir por cero!"),
+        NoSuchResult(index, count) =>
+            format!("No hay resultado #{} ({} resultados hasta ahora).", index, count),
+        NotFinite(value) =>
+            format!("El resultado no es un número finito: {}", Value::Float(value)),
+        FloatInIntegerMode(value) =>
+            format!("No se puede usar {} en modo entero, pruebe :numbers float.",
+                    Value::Float(value)),
+        UnknownFunction(name) => format!("Función desconocida: {}", name),
+        NoArguments(name) => format!("{} necesita al menos un argumento.", name),
+        ArgumentCount(name, 1) => format!("{} recibe 1 argumento.", name),
+        ArgumentCount(name, count) => format!("{} recibe {} argumentos.", name, count),
+        EmptyRange(lo, hi) =>
+            format!("El límite inferior {} es mayor que el superior {}.", lo, hi),
+        UnknownVariable(name) => format!("Variable desconocida: {}", name),
+        RecursionLimitExceeded(limit) =>
+            format!("Llamadas a funciones anidadas a más de {} niveles.", limit),
+        Overflow(width) => format!("Desbordamiento: el resultado no cabe en {}.", width),
+        MismatchedWidths(l, r) => format!("No se puede combinar {} con {}.", l, r),
+        FactorialDomain(value) =>
+            format!("El factorial solo está definido para enteros no negativos, no para {}.",
+                    value),
+        FactorialOverflow(n, largest, width) =>
+            format!("El factorial de {} excede el rango representable: el mayor que cabe en {} \
+                     es {}!", n, width, largest),
+        NotANumber(value) => format!("Se esperaba un número, no {}.", value),
+        NotCompilable => String::from("Solo se puede compilar aritmética entera."),
+        NegativeExponent =>
+            String::from("No se puede elevar un entero a una potencia negativa, \
+                          pruebe :numbers float."),
+        Timeout(timeout) => format!("La evaluación tardó más de {:?}.", timeout),
+        ConstantAssignment(name) => format!("No se puede asignar a la constante {}.", name),
+        NoPreviousResult =>
+            String::from("No hay un resultado anterior al que ans pueda referirse."),
+        NoInverse(divisor, modulus) =>
+            format!("No se puede dividir por {}, que no tiene inverso módulo {}.",
+                    divisor, modulus),
+        IncompatibleUnits(l, r) => format!("No se pueden combinar las unidades {} y {}.", l, r),
+        UnitExponent =>
+            String::from("Solo un entero sin unidades puede ser la potencia de una magnitud."),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use calculator::Calculator;
+    use lexer::lex;
+    use parser::parse;
+    use value::IntType;
+
+    #[test]
+    fn float_in_integer_mode_spells_nan_as_the_input_does() {
+        assert_eq!(eval_message(EvalError::FloatInIntegerMode(f64::NAN)),
+                   "Cannot use nan in integer mode, try :numbers float.");
+        assert_eq!(eval_message_in(EvalError::FloatInIntegerMode(f64::NAN), Language::Spanish),
+                   "No se puede usar nan en modo entero, pruebe :numbers float.");
+        assert_eq!(eval_message(EvalError::FloatInIntegerMode(2.5)),
+                   "Cannot use 2.5 in integer mode, try :numbers float.");
+    }
+
+    #[test]
+    fn overlong_numbers_are_too_large_for_their_width() {
+        let digits = "9".repeat(10_000);
+        let error = match parse(lex(&digits)) {
+            Err(error @ SyntaxError::NumberTooLarge(..)) => error,
+            _ => panic!("the number should be too large"),
+        };
+        assert_eq!(syntax_message(error), format!("Number too large for i32: {}... (10000 digits)",
+                                                  "9".repeat(10)));
+        assert!(parse(lex("300i8")).is_ok());
+        assert!(matches!(parse(lex("3000000000000000000000i64")),
+                         Err(SyntaxError::NumberTooLarge(_, IntType::I64))));
+    }
+
+    #[test]
+    fn switching_language_translates_messages() {
+        let mut calculator = Calculator::new();
+        let message = |calculator: &Calculator| {
+            eval_message_in(EvalError::DivisionByZero, calculator.settings.language)
+        };
+        assert_eq!(message(&calculator), "Cannot divide by zero!");
+        assert_eq!(calculator.command(":lang es"), "Mensajes en español.");
+        assert_eq!(message(&calculator), "¡No se puede dividir por cero!");
+        assert_eq!(syntax_message_in(SyntaxError::MissingOperand, calculator.settings.language),
+                   "A un operador le falta un operando.");
+        calculator.command(":lang en");
+        assert_eq!(message(&calculator), "Cannot divide by zero!");
+        assert!(matches!(Language::from_code("es_ES.UTF-8"), Some(Language::Spanish)));
+        assert!(Language::from_code("fr").is_none());
+    }
+}
@@ -0,0 +1,1157 @@
+use std::boxed::Box;
+use std::collections::{BTreeSet, HashMap};
+use std::collections::LinkedList;
+use std::fmt;
+
+use lexer::{is_operand, lex, FloatBits, Token};
+use value::{IntType, Units};
+use visitor::{dispatch, ExprVisitor};
+
+#[derive(PartialEq, Eq, Hash)]
+pub enum Associativity { LEFT, RIGHT, }
+
+impl fmt::Display for Associativity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Associativity::LEFT => write!(f, "left"),
+            Associativity::RIGHT => write!(f, "right"),
+        }
+    }
+}
+
+pub enum SyntaxError {
+    MalformedNumber(String),
+    /// An integer literal with too many digits for its width.
+    NumberTooLarge(String, IntType),
+    UnknownSymbol(String),
+    MismatchedParentheses,
+    MissingOperand,
+    TooManyOperands,
+    UnexpectedEnd,
+    /// An operator which has been switched off with `:operators`.
+    OperatorDisabled(String),
+    GeneralError,
+}
+
+impl SyntaxError {
+    /// A short code identifying the kind of error, for tools which match
+    /// on errors rather than their messages. Codes never change meaning,
+    /// and a new kind of error takes the next unused one.
+    pub fn code(&self) -> &'static str {
+        use SyntaxError::*;
+        match *self {
+            UnknownSymbol(_) => "E001",
+            MismatchedParentheses => "E002",
+            MalformedNumber(_) => "E003",
+            NumberTooLarge(..) => "E004",
+            MissingOperand => "E005",
+            TooManyOperands => "E006",
+            UnexpectedEnd => "E007",
+            OperatorDisabled(_) => "E008",
+            GeneralError => "E009",
+        }
+    }
+}
+
+/// The relation tested by a comparison operator.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Comparison { Less, LessEqual, Greater, GreaterEqual, Equal, NotEqual, }
+
+impl Comparison {
+    pub fn symbol(self) -> &'static str {
+        match self {
+            Comparison::Less => "<",
+            Comparison::LessEqual => "<=",
+            Comparison::Greater => ">",
+            Comparison::GreaterEqual => ">=",
+            Comparison::Equal => "==",
+            Comparison::NotEqual => "!=",
+        }
+    }
+}
+
+pub enum Expr {
+    Number(i32),
+    Float(f64),
+    Typed(i64, IntType),
+    /// A quantity with a unit, such as `5 m`.
+    Quantity(f64, Units),
+    Previous(usize),
+    Var(String),
+    /// The memory register, `MR`.
+    Memory,
+    Plus(Box<Expr>, Box<Expr>),
+    Minus(Box<Expr>, Box<Expr>),
+    Times(Box<Expr>, Box<Expr>),
+    Divide(Box<Expr>, Box<Expr>),
+    Modulo(Box<Expr>, Box<Expr>),
+    Pow(Box<Expr>, Box<Expr>),
+    Negate(Box<Expr>),
+    Factorial(Box<Expr>),
+    Compare(Comparison, Box<Expr>, Box<Expr>),
+    Call(String, Vec<Expr>),
+}
+
+impl Expr {
+    /// Render the expression in postfix notation, with each operator after
+    /// its operands: `(1 + 2) * 3` is `1 2 + 3 *`. `parse_rpn` reads this
+    /// back, except for what RPN input cannot express: negation, which is
+    /// written `neg`, calls, written as the arguments followed by the
+    /// function's name and their count, as in `1 2 max/2`, and a negative
+    /// literal after an operand, as in `2 -5 *`, which lexes as `2 - 5 *`.
+    pub fn to_postfix_string(&self) -> String {
+        let mut words = Vec::new();
+        postfix(self, &mut words);
+        words.join(" ")
+    }
+
+    /// How tightly the outermost operation of the expression binds, as in
+    /// `op_table`, with postfix `!` above every other operator and
+    /// anything that needs no parentheses above that.
+    fn binding(&self) -> u32 {
+        use Expr::*;
+        match *self {
+            Compare(..) => 1,
+            Plus(..) | Minus(..) => 2,
+            Times(..) | Divide(..) | Modulo(..) => 3,
+            Negate(_) => 4,
+            Pow(..) => 5,
+            Factorial(_) => 6,
+            _ => 7,
+        }
+    }
+}
+
+impl fmt::Display for Expr {
+    /// Render an expression in infix notation, with parentheses only where
+    /// precedence and associativity need them, so that it parses back to
+    /// the same tree: `(1 + 2) * 3`, but `1 + 2 * 3`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut writer = InfixWriter { out: SExprWriter(String::new()), binding: 0 };
+        writer.walk(self);
+        f.write_str(&writer.out.0)
+    }
+}
+
+/// A line of input, once parsed.
+pub enum Statement {
+    Expression(Expr),
+    /// `name ( params ) = body`
+    Definition(String, Function),
+    /// `name = expr`, or `a = b = expr` to assign to each of several names.
+    /// Assignment is right-associative, so the names are assigned from
+    /// right to left.
+    Assignment(Vec<String>, Expr),
+}
+
+/// A function defined during the session.
+pub struct Function {
+    pub params: Vec<String>,
+    pub body: Expr,
+}
+
+
+/// The error for the leftmost token which `lex` could not make sense of,
+/// if any. Checking for these before parsing means that an input with
+/// several bad symbols always reports the first of them, whatever other
+/// mistakes it contains. The error comes with the index of its token.
+pub(crate) fn lexical_error(tokens: &LinkedList<Token>) -> Option<(SyntaxError, usize)> {
+    tokens.iter().enumerate().find_map(|(index, token)| match *token {
+        Token::UnknownToken(ref error) => Some((SyntaxError::UnknownSymbol(error.clone()), index)),
+        Token::LexicalError(ref error) =>
+            Some((SyntaxError::MalformedNumber(error.clone()), index)),
+        Token::LexicalOverflow(ref number, width) =>
+            Some((SyntaxError::NumberTooLarge(number.clone(), width), index)),
+        _ => None,
+    })
+}
+
+
+/// Read the left-hand side of a function definition, `name ( params )`,
+/// returning the name and the parameter names. An error comes with the
+/// index of the token it was found at, which is the number of tokens if
+/// the signature ends too soon.
+pub(crate) fn parse_signature(tokens: LinkedList<Token>)
+        -> Result<(String, Vec<String>), (SyntaxError, usize)> {
+    use SyntaxError::*;
+    use Token::*;
+    let mut tokens = tokens.into_iter();
+    // The index of the token read last.
+    let mut index = 0;
+    let name = match (tokens.next(), tokens.next()) {
+        (Some(Identifier(name)), Some(LPAREN)) => name,
+        (Some(Identifier(_)), _) => return Err((GeneralError, 1)),
+        _ => return Err((GeneralError, 0)),
+    };
+    index += 1;
+    let mut params: Vec<String> = Vec::new();
+    loop {
+        index += 1;
+        match tokens.next() {
+            Some(RPAREN) if params.is_empty() => break,
+            Some(Identifier(param)) => {
+                if params.contains(&param) {
+                    return Err((GeneralError, index));
+                }
+                params.push(param);
+            },
+            _ => return Err((GeneralError, index)),
+        }
+        index += 1;
+        match tokens.next() {
+            Some(COMMA) => continue,
+            Some(RPAREN) => break,
+            _ => return Err((GeneralError, index)),
+        }
+    }
+    if tokens.next().is_some() {
+        return Err((GeneralError, index + 1));
+    }
+    Ok((name, params))
+}
+
+
+/// The operator-precedence table used by `parse`. Modulo binds like
+/// multiplication and division, as in most languages, so `6 + 4 % 3` is
+/// `6 + (4 % 3)`.
+pub(crate) fn op_table() -> HashMap<Token, (u32, Associativity)> {
+    use Associativity::*;
+    use Token::*;
+    let mut op_table : HashMap<Token, (u32, Associativity)> = HashMap::new();
+    op_table.insert(POW,    (5, RIGHT));
+    op_table.insert(NEGATE, (4, RIGHT));
+    op_table.insert(IDENTITY, (4, RIGHT));
+    op_table.insert(TIMES,  (3, LEFT));
+    op_table.insert(DIVIDE, (3, LEFT));
+    op_table.insert(MODULO, (3, LEFT));
+    op_table.insert(PLUS,   (2, LEFT));
+    op_table.insert(MINUS,  (2, LEFT));
+    for comparison in [LT, LE, GT, GE, EQ, NE] {
+        op_table.insert(comparison, (1, LEFT));
+    }
+    op_table.insert(LPAREN, (9, LEFT));
+    op_table.insert(RPAREN, (0, LEFT));
+    op_table
+}
+
+
+/// Describe the precedence and associativity of each operator in
+/// `tokens`, in the order they appear. Higher precedence binds tighter.
+pub fn precedence_notes(tokens: &LinkedList<Token>) -> Vec<String> {
+    use Token::*;
+    let op_table = op_table();
+    tokens.iter().filter_map(|token| match *token {
+        LPAREN | RPAREN => None,
+        FACTORIAL => Some(String::from("!: postfix, binds tighter than any other operator")),
+        NEGATE | IDENTITY => op_table.get(token).map(|&(precedence, _)| {
+            format!("{}: unary prefix, precedence {}", token, precedence)
+        }),
+        _ => op_table.get(token).map(|&(precedence, ref associativity)| {
+            format!("{}: precedence {}, {}-associative", token, precedence, associativity)
+        }),
+    }).collect()
+}
+
+
+/// Explain the operator written `symbol`, with its precedence,
+/// associativity and an example, or return `None` if there is no such
+/// operator. Both forms of `+` and `-` are described.
+pub fn operator_help(symbol: &str) -> Option<String> {
+    use Token::*;
+    let mut tokens = lex(symbol);
+    if tokens.len() != 2 {
+        return None;
+    }
+    let forms = match tokens.pop_front()? {
+        PLUS | IDENTITY => vec![PLUS, IDENTITY],
+        MINUS | NEGATE => vec![MINUS, NEGATE],
+        token => vec![token],
+    };
+    let (name, example) = match forms[0] {
+        PLUS => ("addition, or unary plus", "1 + 2 is 3"),
+        MINUS => ("subtraction, or negation", "5 - 2 - 1 is (5 - 2) - 1 = 2, and - 2 ^ 2 is -4"),
+        TIMES => ("multiplication", "2 + 3 * 4 is 14"),
+        DIVIDE => ("division, which truncates integers", "7 / 2 is 3"),
+        MODULO => ("the remainder, also written mod", "1 + 7 % 3 is 2"),
+        POW => ("exponentiation", "2 ^ 3 ^ 2 is 2 ^ (3 ^ 2) = 512"),
+        FACTORIAL => ("the factorial", "5! is 120"),
+        LT => ("less than", "1 + 1 < 3 is true"),
+        LE => ("less than or equal to", "3 <= 3 is true"),
+        GT => ("greater than", "2 > 3 is false"),
+        GE => ("greater than or equal to", "3 >= 2 is true"),
+        EQ => ("equal to", "2 * 2 == 4 is true"),
+        NE => ("not equal to", "2 * 2 != 4 is false"),
+        _ => return None,
+    };
+    let symbol = forms[0].to_string();
+    let notes = precedence_notes(&forms.into_iter().collect());
+    Some(format!("{} is {}.\n{}\nExample: {}", symbol, name, notes.join("\n"), example))
+}
+
+
+/// The pairs of parentheses in infix `input` which could be left out
+/// without changing how it parses, as the byte offsets of each `(` and its
+/// `)`, in the order the `(` appear. In `( ( 1 ) )` both pairs are
+/// redundant, but in `( 1 + 2 ) * 3` the pair is needed. Each pair is
+/// judged on its own, so removing several at once may not be safe. Input
+/// which does not parse has none.
+pub fn redundant_parens(input: &str) -> Vec<(usize, usize)> {
+    let expected = match parse(lex(input)) {
+        Ok(expr) => to_sexpr(&expr),
+        Err(_) => return Vec::new(),
+    };
+    let mut pairs = Vec::new();
+    let mut open = Vec::new();
+    for (offset, c) in input.char_indices() {
+        match c {
+            '(' => open.push(offset),
+            ')' => if let Some(start) = open.pop() {
+                pairs.push((start, offset));
+            },
+            _ => {},
+        }
+    }
+    pairs.sort();
+    pairs.into_iter().filter(|&(start, end)| {
+        // Blank the pair out rather than removing it, so that the tokens
+        // either side of it stay apart.
+        let mut without = String::from(input);
+        without.replace_range(start..=start, " ");
+        without.replace_range(end..=end, " ");
+        matches!(parse(lex(&without)), Ok(expr) if to_sexpr(&expr) == expected)
+    }).collect()
+}
+
+
+pub fn parse(tokens: LinkedList<Token>) -> Result<Expr, SyntaxError> {
+    parse_located(tokens).map_err(|(error, _)| error)
+}
+
+
+/// Parse like `parse`, giving with an error the index of the token it was
+/// found at. An error which only shows at the end of the input, such as a
+/// `(` left open, is at the final `EOF` token.
+pub fn parse_located(tokens: LinkedList<Token>) -> Result<Expr, (SyntaxError, usize)> {
+    if let Some(located) = lexical_error(&tokens) {
+        return Err(located);
+    }
+    let mut consumed = 0;
+    shunting_yard(tokens, &mut consumed).map_err(|error| (error, consumed.saturating_sub(1)))
+}
+
+
+/// The body of `parse`, counting in `consumed` the tokens it has read.
+fn shunting_yard(tokens: LinkedList<Token>, consumed: &mut usize) -> Result<Expr, SyntaxError> {
+    use Associativity::*;
+    use Expr::*;
+    use SyntaxError::*;
+    use Token::*;
+    let op_table = op_table();
+    // Dijkstra's shunting-yard algorithm. Operands are held on a stack, so
+    // the right operand of each operator is always popped first.
+    let mut operator_stack: Vec<Token> = Vec::new();
+    let mut operand_queue: LinkedList<Expr> = LinkedList::new();
+    // One entry per open parenthesis. Those which begin the arguments of a
+    // function call record the function name, the number of operands
+    // queued before the call, and the number of commas seen so far.
+    let mut paren_stack: Vec<Option<(String, usize, usize)>> = Vec::new();
+    let mut terminated = false;
+    // Whether the previous token ended an operand, which `!` must follow.
+    let mut after_operand = false;
+    let mut tokens = tokens.into_iter().peekable();
+    while let Some(token) = tokens.next() {
+        *consumed += 1;
+        debug!("{} | operators: {} | operands: {}",
+               token, join(operator_stack.iter().map(Token::to_string)),
+               join(operand_queue.iter().map(to_sexpr)));
+        let follows_operand = after_operand;
+        after_operand = is_operand(Some(&token));
+        match token {
+            EOF => {
+                terminated = true;
+                break;
+            },
+            UnknownToken(error) => return Err(UnknownSymbol(error)),
+            LexicalError(error) => return Err(MalformedNumber(error)),
+            LexicalOverflow(number, width) => return Err(NumberTooLarge(number, width)),
+            LexicalNumber(number, _) => operand_queue.push_back(Number(number)),
+            LexicalFloat(FloatBits(number), _) => operand_queue.push_back(Expr::Float(number)),
+            LexicalTyped(number, width, _) => operand_queue.push_back(Typed(number, width)),
+            LexicalQuantity(FloatBits(x), units, _) => operand_queue.push_back(Quantity(x, units)),
+            ResultRef(index) => operand_queue.push_back(Previous(index)),
+            MEMORY => operand_queue.push_back(Memory),
+            Identifier(name) => {
+                if tokens.peek() != Some(&LPAREN) {
+                    operand_queue.push_back(Var(name));
+                    continue;
+                }
+                tokens.next();
+                *consumed += 1;
+                after_operand = false;  // The call's LPAREN.
+                paren_stack.push(Some((name, operand_queue.len(), 0)));
+                operator_stack.push(LPAREN);
+            },
+            LPAREN => {
+                paren_stack.push(None);
+                operator_stack.push(LPAREN);
+            },
+            COMMA => {
+                while operator_stack.last().is_some() &&
+                      *operator_stack.last().unwrap() != LPAREN {
+                    reduce(operator_stack.pop(), &mut operand_queue)?;
+                };
+                match paren_stack.last_mut() {
+                    // Each argument is exactly one operand, so the comma must
+                    // follow one more than the previous comma did.
+                    Some(&mut Some((_, queued, ref mut commas)))
+                        if operand_queue.len() == queued + *commas + 1 => *commas += 1,
+                    // A misplaced comma, or one outside a function call.
+                    _ => return Err(GeneralError),
+                }
+            },
+            RPAREN => {
+                while operator_stack.last().is_some() &&
+                      *operator_stack.last().unwrap() != LPAREN {
+                    reduce(operator_stack.pop(), &mut operand_queue)?;
+                };
+                if operator_stack.is_empty() {
+                    return Err(MismatchedParentheses);
+                }
+                operator_stack.pop();  // Remove matching LPAREN.
+                if let Some(Some((name, queued, commas))) = paren_stack.pop() {
+                    // Every argument has been reduced to a single operand.
+                    let count = match operand_queue.len().checked_sub(queued) {
+                        Some(count) => count,
+                        None => return Err(GeneralError),
+                    };
+                    if count != commas + 1 && !(count == 0 && commas == 0) {
+                        return Err(GeneralError);
+                    }
+                    let args = operand_queue.split_off(queued).into_iter().collect();
+                    operand_queue.push_back(Call(name, args));
+                }
+            },
+            NEGATE => operator_stack.push(NEGATE),  // Prefix, so nothing to reduce yet.
+            IDENTITY => operator_stack.push(IDENTITY),
+            // Postfix and binding tighter than anything else, so it applies
+            // straight away to the operand before it, which must be the
+            // previous token: in `3 + ! 4` it has none.
+            FACTORIAL if !follows_operand => return Err(MissingOperand),
+            FACTORIAL => match operand_queue.pop_back() {
+                Some(operand) => operand_queue.push_back(Factorial(Box::new(operand))),
+                None => return Err(MissingOperand),
+            },
+            // Assignment is split off by `parse_statement`, so an `=` left in
+            // an expression is misplaced, as in `1 + 2 = 3`.
+            ASSIGN => return Err(GeneralError),
+            operator => {
+                while let Some(op2) = operator_stack.pop() {
+                    let (p1, ref a1) = op_table[&operator];
+                    let (p2, _) = op_table[&op2];
+                    if op2 != LPAREN &&
+                       ((p1 < p2 && *a1 == RIGHT) || (p1 <= p2 && *a1 == LEFT)) {
+                        reduce(Some(op2), &mut operand_queue)?;
+                    } else {
+                        operator_stack.push(op2);
+                        break;
+                    }
+                }
+                operator_stack.push(operator);
+            },
+        }
+    }
+    // All tokens have been consumed from user input.
+    if !terminated {
+        return Err(UnexpectedEnd);
+    }
+    while !operator_stack.is_empty() {
+        match operator_stack.pop() {
+            None => return Err(GeneralError),
+            Some(LPAREN) => return Err(MismatchedParentheses),
+            Some(RPAREN) => return Err(MismatchedParentheses),
+            Some(LexicalError(error)) => return Err(UnknownSymbol(error)),
+            operator => reduce(operator, &mut operand_queue)?,
+        };
+    };
+    match operand_queue.len() {
+        0 => Err(MissingOperand),
+        1 => Ok(operand_queue.pop_front().unwrap()),
+        _ => Err(TooManyOperands),
+    }
+}
+
+
+/// Build an expression from tokens in Reverse Polish notation. The tokens
+/// are already in evaluation order, so each operator simply combines the
+/// two operands on top of the stack.
+///
+/// Every `-` is subtraction and every `+` addition in RPN. A negative
+/// literal such as `-5` can only be written where `lex` sees a value
+/// position, i.e. at the start of the input or after an operator.
+pub fn parse_rpn(tokens: LinkedList<Token>) -> Result<Expr, SyntaxError> {
+    parse_rpn_located(tokens).map_err(|(error, _)| error)
+}
+
+
+/// Parse RPN like `parse_rpn`, giving with an error the index of the token
+/// it was found at, as `parse_located` does.
+pub fn parse_rpn_located(tokens: LinkedList<Token>) -> Result<Expr, (SyntaxError, usize)> {
+    if let Some(located) = lexical_error(&tokens) {
+        return Err(located);
+    }
+    let mut consumed = 0;
+    postfix_stack(tokens, &mut consumed).map_err(|error| (error, consumed.saturating_sub(1)))
+}
+
+
+/// The body of `parse_rpn`, counting in `consumed` the tokens it has read.
+fn postfix_stack(tokens: LinkedList<Token>, consumed: &mut usize) -> Result<Expr, SyntaxError> {
+    use Expr::*;
+    use SyntaxError::*;
+    use Token::*;
+    let mut operand_stack: LinkedList<Expr> = LinkedList::new();
+    let mut terminated = false;
+    for token in tokens {
+        *consumed += 1;
+        debug!("{} | operands: {}", token, join(operand_stack.iter().map(to_sexpr)));
+        match token {
+            EOF => {
+                terminated = true;
+                break;
+            },
+            UnknownToken(error) => return Err(UnknownSymbol(error)),
+            LexicalError(error) => return Err(MalformedNumber(error)),
+            LexicalOverflow(number, width) => return Err(NumberTooLarge(number, width)),
+            LexicalNumber(number, _) => operand_stack.push_back(Number(number)),
+            LexicalFloat(FloatBits(number), _) => operand_stack.push_back(Expr::Float(number)),
+            LexicalTyped(number, width, _) => operand_stack.push_back(Typed(number, width)),
+            LexicalQuantity(FloatBits(x), units, _) => operand_stack.push_back(Quantity(x, units)),
+            ResultRef(index) => operand_stack.push_back(Previous(index)),
+            MEMORY => operand_stack.push_back(Memory),
+            Identifier(name) => operand_stack.push_back(Var(name)),
+            token @ (LPAREN | RPAREN | COMMA | ASSIGN) =>
+                return Err(UnknownSymbol(token.to_string())),
+            FACTORIAL => match operand_stack.pop_back() {
+                Some(operand) => operand_stack.push_back(Factorial(Box::new(operand))),
+                None => return Err(MissingOperand),
+            },
+            operator => {
+                let operator = match operator {
+                    NEGATE => MINUS,
+                    IDENTITY => PLUS,
+                    operator => operator,
+                };
+                let expr = construct_expr(Some(operator), &mut operand_stack)?;
+                operand_stack.push_back(expr);
+            },
+        }
+    }
+    if !terminated {
+        return Err(UnexpectedEnd);
+    }
+    match operand_stack.len() {
+        0 => Err(MissingOperand),
+        1 => Ok(operand_stack.pop_back().unwrap()),
+        _ => Err(TooManyOperands),
+    }
+}
+
+
+/// Replace the operands of `operator` at the back of the queue with the
+/// expression it builds from them, or return `MissingOperand` if there are
+/// too few of them.
+fn reduce(operator: Option<Token>, operand_queue: &mut LinkedList<Expr>) -> Result<(), SyntaxError> {
+    use SyntaxError::MissingOperand;
+    if operator == Some(Token::NEGATE) {
+        let operand = operand_queue.pop_back().ok_or(MissingOperand)?;
+        operand_queue.push_back(Expr::Negate(Box::new(operand)));
+        debug!("reduced to {}", to_sexpr(operand_queue.back().unwrap()));
+        return Ok(());
+    }
+    if operator == Some(Token::IDENTITY) {
+        // The operand is already in place.
+        return if operand_queue.is_empty() { Err(MissingOperand) } else { Ok(()) };
+    }
+    let expr = construct_expr(operator, operand_queue)?;
+    operand_queue.push_back(expr);
+    debug!("reduced to {}", to_sexpr(operand_queue.back().unwrap()));
+    Ok(())
+}
+
+
+/// Join the items of a stack, bottom first, for logging.
+fn join(items: impl Iterator<Item=String>) -> String {
+    items.collect::<Vec<String>>().join(" ")
+}
+
+
+/// Build the expression for the binary operator `token` from the two
+/// operands at the back of `operands`, removing them. Returns
+/// `MissingOperand`, without removing anything, if there are fewer than
+/// two.
+fn construct_expr(token: Option<Token>,
+                  operands: &mut LinkedList<Expr>) -> Result<Expr, SyntaxError> {
+    use Expr::*;
+    use SyntaxError::*;
+    use Token::*;
+    if operands.len() < 2 {
+        return Err(MissingOperand);
+    }
+    let r_op = operands.pop_back().unwrap();
+    let l_op = operands.pop_back().unwrap();
+    let expr : Expr = match token {
+        Some(POW) => Pow(Box::new(l_op), Box::new(r_op)),
+        Some(DIVIDE) => Divide(Box::new(l_op), Box::new(r_op)),
+        Some(TIMES) => Times(Box::new(l_op), Box::new(r_op)),
+        Some(PLUS) => Plus(Box::new(l_op), Box::new(r_op)),
+        Some(MINUS) => Minus(Box::new(l_op), Box::new(r_op)),
+        Some(MODULO) => Modulo(Box::new(l_op), Box::new(r_op)),
+        Some(LT) => Compare(Comparison::Less, Box::new(l_op), Box::new(r_op)),
+        Some(LE) => Compare(Comparison::LessEqual, Box::new(l_op), Box::new(r_op)),
+        Some(GT) => Compare(Comparison::Greater, Box::new(l_op), Box::new(r_op)),
+        Some(GE) => Compare(Comparison::GreaterEqual, Box::new(l_op), Box::new(r_op)),
+        Some(EQ) => Compare(Comparison::Equal, Box::new(l_op), Box::new(r_op)),
+        Some(NE) => Compare(Comparison::NotEqual, Box::new(l_op), Box::new(r_op)),
+        Some(LexicalError(error)) => return Err(UnknownSymbol(error)),
+        _ => return  Err(GeneralError),
+    };
+    Ok(expr)
+}
+
+
+/// Render an expression in infix notation with every operation in
+/// parentheses, showing how it was grouped: `1 + 2 * 3` is
+/// `(1 + (2 * 3))`.
+pub fn to_grouped(expr: &Expr) -> String {
+    use Expr::*;
+    let (l, symbol, r) = match *expr {
+        Plus(ref l, ref r) => (l, "+", r),
+        Minus(ref l, ref r) => (l, "-", r),
+        Times(ref l, ref r) => (l, "*", r),
+        Divide(ref l, ref r) => (l, "/", r),
+        Modulo(ref l, ref r) => (l, "%", r),
+        Pow(ref l, ref r) => (l, "^", r),
+        Compare(comparison, ref l, ref r) => (l, comparison.symbol(), r),
+        Negate(ref operand) => return format!("(-{})", to_grouped(operand)),
+        Factorial(ref operand) => return format!("({}!)", to_grouped(operand)),
+        Call(ref name, ref args) => {
+            let args: Vec<String> = args.iter().map(to_grouped).collect();
+            return format!("{}({})", name, args.join(", "));
+        },
+        _ => return to_sexpr(expr),
+    };
+    format!("({} {} {})", to_grouped(l), symbol, to_grouped(r))
+}
+
+
+/// Add the words of `expr` in postfix notation to `words`.
+fn postfix(expr: &Expr, words: &mut Vec<String>) {
+    use Expr::*;
+    let (operands, operator): (Vec<&Expr>, String) = match *expr {
+        Plus(ref l, ref r) => (vec![l, r], String::from("+")),
+        Minus(ref l, ref r) => (vec![l, r], String::from("-")),
+        Times(ref l, ref r) => (vec![l, r], String::from("*")),
+        Divide(ref l, ref r) => (vec![l, r], String::from("/")),
+        Modulo(ref l, ref r) => (vec![l, r], String::from("%")),
+        Pow(ref l, ref r) => (vec![l, r], String::from("^")),
+        Compare(comparison, ref l, ref r) => (vec![l, r], String::from(comparison.symbol())),
+        Negate(ref operand) => (vec![operand], String::from("neg")),
+        Factorial(ref operand) => (vec![operand], String::from("!")),
+        Call(ref name, ref args) => (args.iter().collect(), format!("{}/{}", name, args.len())),
+        _ => (Vec::new(), to_sexpr(expr)),
+    };
+    for operand in operands {
+        postfix(operand, words);
+    }
+    words.push(operator);
+}
+
+
+/// Draw an expression as a tree, one node per line with its children
+/// indented below it:
+///
+/// ```text
+/// +
+/// ├─ 1
+/// └─ *
+///    ├─ 2
+///    └─ 3
+/// ```
+///
+/// Each level is indented by three columns, so deep trees stay narrow.
+pub fn to_tree(expr: &Expr) -> String {
+    let mut lines = Vec::new();
+    draw(expr, "", "", &mut lines);
+    lines.join("\n")
+}
+
+
+/// Add the lines for `expr` and its children to `lines`. The first line is
+/// prefixed with `first` and the rest with `rest`.
+fn draw(expr: &Expr, first: &str, rest: &str, lines: &mut Vec<String>) {
+    use Expr::*;
+    let (label, children): (&str, Vec<&Expr>) = match *expr {
+        Plus(ref l, ref r) => ("+", vec![l, r]),
+        Minus(ref l, ref r) => ("-", vec![l, r]),
+        Times(ref l, ref r) => ("*", vec![l, r]),
+        Divide(ref l, ref r) => ("/", vec![l, r]),
+        Modulo(ref l, ref r) => ("%", vec![l, r]),
+        Pow(ref l, ref r) => ("^", vec![l, r]),
+        Compare(comparison, ref l, ref r) => (comparison.symbol(), vec![l, r]),
+        Negate(ref operand) => ("-", vec![operand]),
+        Factorial(ref operand) => ("!", vec![operand]),
+        Call(ref name, ref args) => (name.as_str(), args.iter().collect()),
+        _ => {
+            lines.push(format!("{}{}", first, to_sexpr(expr)));
+            return;
+        },
+    };
+    lines.push(format!("{}{}", first, label));
+    for (index, child) in children.iter().enumerate() {
+        if index + 1 == children.len() {
+            draw(child, &format!("{}└─ ", rest), &format!("{}   ", rest), lines);
+        } else {
+            draw(child, &format!("{}├─ ", rest), &format!("{}│  ", rest), lines);
+        }
+    }
+}
+
+
+/// Render an expression as a Lisp-style s-expression, with each operator
+/// or function name in prefix position: `1 + 2 * 3` is `(+ 1 (* 2 3))`.
+pub fn to_sexpr(expr: &Expr) -> String {
+    let mut writer = SExprWriter(String::new());
+    writer.walk(expr);
+    writer.0
+}
+
+
+struct SExprWriter(String);
+
+impl SExprWriter {
+    fn operation(&mut self, symbol: &str, operands: &[&Expr]) {
+        self.0.push('(');
+        self.0.push_str(symbol);
+        for operand in operands {
+            self.0.push(' ');
+            self.walk(operand);
+        }
+        self.0.push(')');
+    }
+}
+
+impl ExprVisitor for SExprWriter {
+    fn visit_number(&mut self, n: i32) {
+        self.0.push_str(&n.to_string());
+    }
+
+    fn visit_float(&mut self, x: f64) {
+        if x.is_nan() {
+            self.0.push_str("nan");
+        } else {
+            self.0.push_str(&format!("{:?}", x));
+        }
+    }
+
+    fn visit_typed(&mut self, n: i64, width: IntType) {
+        self.0.push_str(&format!("{}{}", n, width));
+    }
+
+    fn visit_quantity(&mut self, x: f64, units: Units) {
+        self.0.push_str(&format!("{:?} {}", x, units));
+    }
+
+    fn visit_previous(&mut self, index: usize) {
+        self.0.push_str(&format!("#{}", index));
+    }
+
+    fn visit_var(&mut self, name: &str) {
+        self.0.push_str(name);
+    }
+
+    fn visit_memory(&mut self) {
+        self.0.push_str("MR");
+    }
+
+    fn visit_plus(&mut self, l: &Expr, r: &Expr) { self.operation("+", &[l, r]); }
+    fn visit_minus(&mut self, l: &Expr, r: &Expr) { self.operation("-", &[l, r]); }
+    fn visit_times(&mut self, l: &Expr, r: &Expr) { self.operation("*", &[l, r]); }
+    fn visit_divide(&mut self, l: &Expr, r: &Expr) { self.operation("/", &[l, r]); }
+    fn visit_modulo(&mut self, l: &Expr, r: &Expr) { self.operation("%", &[l, r]); }
+    fn visit_pow(&mut self, l: &Expr, r: &Expr) { self.operation("^", &[l, r]); }
+    fn visit_negate(&mut self, operand: &Expr) { self.operation("-", &[operand]); }
+    fn visit_factorial(&mut self, operand: &Expr) { self.operation("!", &[operand]); }
+
+    fn visit_compare(&mut self, comparison: Comparison, l: &Expr, r: &Expr) {
+        self.operation(comparison.symbol(), &[l, r]);
+    }
+
+    fn visit_call(&mut self, name: &str, args: &[Expr]) {
+        let args: Vec<&Expr> = args.iter().collect();
+        self.operation(name, &args);
+    }
+}
+
+
+/// Writes the infix form of an expression for `Display`. Leaves are
+/// written as in an s-expression.
+struct InfixWriter {
+    out: SExprWriter,
+    /// How tightly the node being written binds, as `Expr::binding` gives.
+    binding: u32,
+}
+
+impl InfixWriter {
+    fn operand(&mut self, operand: &Expr, parenthesize: bool) {
+        if parenthesize {
+            self.out.0.push('(');
+            self.walk(operand);
+            self.out.0.push(')');
+        } else {
+            self.walk(operand);
+        }
+    }
+
+    fn binary(&mut self, l: &Expr, symbol: &str, r: &Expr, associativity: Associativity) {
+        let binding = self.binding;
+        self.operand(l, l.binding() < binding ||
+                        (l.binding() == binding && associativity == Associativity::RIGHT));
+        self.out.0.push_str(&format!(" {} ", symbol));
+        self.operand(r, r.binding() < binding ||
+                        (r.binding() == binding && associativity == Associativity::LEFT));
+    }
+}
+
+impl ExprVisitor for InfixWriter {
+    fn walk(&mut self, expr: &Expr) {
+        let outer = self.binding;
+        self.binding = expr.binding();
+        dispatch(self, expr);
+        self.binding = outer;
+    }
+
+    fn visit_number(&mut self, n: i32) { self.out.visit_number(n); }
+    fn visit_float(&mut self, x: f64) { self.out.visit_float(x); }
+    fn visit_typed(&mut self, n: i64, width: IntType) { self.out.visit_typed(n, width); }
+    fn visit_quantity(&mut self, x: f64, units: Units) { self.out.visit_quantity(x, units); }
+    fn visit_previous(&mut self, index: usize) { self.out.visit_previous(index); }
+    fn visit_var(&mut self, name: &str) { self.out.visit_var(name); }
+    fn visit_memory(&mut self) { self.out.visit_memory(); }
+
+    fn visit_plus(&mut self, l: &Expr, r: &Expr) { self.binary(l, "+", r, Associativity::LEFT); }
+    fn visit_minus(&mut self, l: &Expr, r: &Expr) { self.binary(l, "-", r, Associativity::LEFT); }
+    fn visit_times(&mut self, l: &Expr, r: &Expr) { self.binary(l, "*", r, Associativity::LEFT); }
+    fn visit_divide(&mut self, l: &Expr, r: &Expr) { self.binary(l, "/", r, Associativity::LEFT); }
+    fn visit_modulo(&mut self, l: &Expr, r: &Expr) { self.binary(l, "%", r, Associativity::LEFT); }
+    fn visit_pow(&mut self, l: &Expr, r: &Expr) { self.binary(l, "^", r, Associativity::RIGHT); }
+
+    fn visit_compare(&mut self, comparison: Comparison, l: &Expr, r: &Expr) {
+        self.binary(l, comparison.symbol(), r, Associativity::LEFT);
+    }
+
+    fn visit_negate(&mut self, operand: &Expr) {
+        self.out.0.push_str("- ");
+        self.operand(operand, operand.binding() < 4);
+    }
+
+    fn visit_factorial(&mut self, operand: &Expr) {
+        self.operand(operand, operand.binding() < 6);
+        self.out.0.push('!');
+    }
+
+    fn visit_call(&mut self, name: &str, args: &[Expr]) {
+        self.out.0.push_str(name);
+        self.out.0.push('(');
+        for (i, arg) in args.iter().enumerate() {
+            if i > 0 {
+                self.out.0.push_str(", ");
+            }
+            self.walk(arg);
+        }
+        self.out.0.push(')');
+    }
+}
+
+
+/// The names of all the variables used in an expression, which must be
+/// bound before it can be evaluated. Function names are not included.
+pub fn free_vars(expr: &Expr) -> BTreeSet<String> {
+    let mut collector = VarCollector(BTreeSet::new());
+    collector.walk(expr);
+    collector.0
+}
+
+
+struct VarCollector(BTreeSet<String>);
+
+impl ExprVisitor for VarCollector {
+    fn visit_var(&mut self, name: &str) {
+        self.0.insert(String::from(name));
+    }
+}
+
+
+/// The size of an expression tree.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct ExprStats {
+    /// The number of nodes on the longest path from the root to a leaf, so
+    /// a single number has depth 1.
+    pub depth: usize,
+    /// The total number of nodes.
+    pub nodes: usize,
+}
+
+
+/// Measure the depth and number of nodes of an expression.
+pub fn stats(expr: &Expr) -> ExprStats {
+    let mut counter = StatsCounter { depth: 0, stats: ExprStats { depth: 0, nodes: 0 } };
+    counter.walk(expr);
+    counter.stats
+}
+
+
+struct StatsCounter {
+    /// The depth of the node being visited.
+    depth: usize,
+    stats: ExprStats,
+}
+
+impl ExprVisitor for StatsCounter {
+    fn walk(&mut self, expr: &Expr) {
+        self.depth += 1;
+        self.stats.nodes += 1;
+        self.stats.depth = self.stats.depth.max(self.depth);
+        dispatch(self, expr);
+        self.depth -= 1;
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lexer::lex;
+
+    /// The s-expression for infix `input`.
+    fn sexpr(input: &str) -> String {
+        match parse(lex(input)) {
+            Ok(expr) => to_sexpr(&expr),
+            Err(_) => panic!("{} should parse", input),
+        }
+    }
+
+    /// The error from parsing `input`, and the index of its token.
+    fn located_error(input: &str) -> (SyntaxError, usize) {
+        match parse_located(lex(input)) {
+            Ok(_) => panic!("{} should not parse", input),
+            Err(located) => located,
+        }
+    }
+
+    #[test]
+    fn errors_are_located_at_their_token() {
+        assert!(matches!(located_error("1 + 2 )"), (SyntaxError::MismatchedParentheses, 3)));
+        assert!(matches!(located_error("1 + $"), (SyntaxError::UnknownSymbol(_), 2)));
+        // The `EOF` token follows the four tokens of the input.
+        assert!(matches!(located_error("( 1 + 2"), (SyntaxError::MismatchedParentheses, 4)));
+        assert!(matches!(parse_rpn_located(lex("1 2 3 +")),
+                         Err((SyntaxError::TooManyOperands, 4))));
+    }
+
+    #[test]
+    fn modulo_binds_like_multiplication() {
+        assert_eq!(sexpr("6 + 4 % 3"), "(+ 6 (% 4 3))");
+        assert_eq!(sexpr("2 * 3 % 4"), "(% (* 2 3) 4)");
+        assert_eq!(sexpr("8 % 3 * 2"), "(* (% 8 3) 2)");
+    }
+
+    #[test]
+    fn sexpr_nests_operators_with_their_symbols() {
+        assert_eq!(sexpr("1 + 2 * 3 ^ 4 - 5"), "(- (+ 1 (* 2 (^ 3 4))) 5)");
+        assert_eq!(sexpr("( 1 + 2 ) * 3 % 4"), "(% (* (+ 1 2) 3) 4)");
+        assert_eq!(sexpr("( 2 ^ 3 ^ 2 ) / 5"), "(/ (^ 2 (^ 3 2)) 5)");
+        assert_eq!(sexpr("sum ( 1 , 2 ) * 4"), "(* (sum 1 2) 4)");
+    }
+
+    #[test]
+    fn trailing_input_is_a_specific_error() {
+        assert!(matches!(parse(lex("1 + 2 3")), Err(SyntaxError::TooManyOperands)));
+        assert!(matches!(parse(lex("1 + 2 )")), Err(SyntaxError::MismatchedParentheses)));
+        let mut unterminated = lex("1 + 2");
+        unterminated.pop_back();
+        assert!(matches!(parse(unterminated), Err(SyntaxError::UnexpectedEnd)));
+    }
+
+    #[test]
+    fn mod_is_an_alias_for_percent() {
+        assert!(lex("7 mod 3") == lex("7 % 3"));
+        assert_eq!(sexpr("6 + 4 mod 3 * 2"), sexpr("6 + 4 % 3 * 2"));
+        assert_eq!(sexpr("2 * 9 mod 4"), "(% (* 2 9) 4)");
+    }
+
+    #[test]
+    fn the_leftmost_lexical_error_is_reported() {
+        let symbol = |result| match result {
+            Err(SyntaxError::UnknownSymbol(symbol)) => symbol,
+            _ => panic!("the input should have an unknown symbol"),
+        };
+        assert_eq!(symbol(parse(lex("1 @ 2 $ 3"))), "@");
+        assert_eq!(symbol(parse(lex("( 1 $ 2 @"))), "$");
+        assert_eq!(symbol(parse_rpn(lex("1 2 @ $"))), "@");
+    }
+
+    #[test]
+    fn dangling_operators_are_missing_operands() {
+        for input in ["1 + 2 +", "1 * ", "^ 2", "* * 3", "-"] {
+            assert!(matches!(parse(lex(input)), Err(SyntaxError::MissingOperand)), "{}", input);
+        }
+    }
+
+    #[test]
+    fn precedence_notes_list_each_operator_in_order() {
+        assert_eq!(precedence_notes(&lex("1 + 2 * 3 ^ 4")),
+                   vec!["+: precedence 2, left-associative",
+                        "*: precedence 3, left-associative",
+                        "^: precedence 5, right-associative"]);
+    }
+
+    #[test]
+    fn free_vars_are_collected_once_each() {
+        let expr = parse(lex("x + y * x")).ok().expect("the expression should parse");
+        let names: Vec<String> = free_vars(&expr).into_iter().collect();
+        assert_eq!(names, vec!["x", "y"]);
+        let constant = parse(lex("1 + 2")).ok().expect("the expression should parse");
+        assert!(free_vars(&constant).is_empty());
+    }
+
+    #[test]
+    fn constructing_without_two_operands_is_an_error() {
+        let mut operands = LinkedList::new();
+        assert!(matches!(construct_expr(Some(Token::PLUS), &mut operands),
+                         Err(SyntaxError::MissingOperand)));
+        operands.push_back(Expr::Number(1));
+        assert!(matches!(construct_expr(Some(Token::PLUS), &mut operands),
+                         Err(SyntaxError::MissingOperand)));
+        assert_eq!(operands.len(), 1);
+        assert!(matches!(reduce(Some(Token::NEGATE), &mut LinkedList::new()),
+                         Err(SyntaxError::MissingOperand)));
+        assert!(matches!(parse(lex("1 +")), Err(SyntaxError::MissingOperand)));
+        assert!(matches!(parse(lex("* 2")), Err(SyntaxError::MissingOperand)));
+    }
+
+    #[test]
+    fn stats_give_depth_and_node_count() {
+        let measure = |input| stats(&parse(lex(input)).ok().expect("the input should parse"));
+        assert!(measure("7") == ExprStats { depth: 1, nodes: 1 });
+        assert!(measure("1 + 2 * 3") == ExprStats { depth: 3, nodes: 5 });
+        assert!(measure("( ( ( 1 + 2 ) + 3 ) + 4 ) + 5") == ExprStats { depth: 5, nodes: 9 });
+        assert!(measure("max ( 1 , - 2 )") == ExprStats { depth: 3, nodes: 4 });
+    }
+
+    #[test]
+    fn long_subtraction_and_division_chains_group_to_the_left() {
+        assert_eq!(sexpr("1 - 2 - 3 - 4"), "(- (- (- 1 2) 3) 4)");
+        assert_eq!(sexpr("64 / 4 / 2 / 2"), "(/ (/ (/ 64 4) 2) 2)");
+        assert_eq!(sexpr("2 - 3 - 4 - 5 - 6"), "(- (- (- (- 2 3) 4) 5) 6)");
+    }
+
+    #[test]
+    fn operator_help_describes_one_operator() {
+        assert_eq!(operator_help("^").as_deref(),
+                   Some("^ is exponentiation.\n^: precedence 5, right-associative\n\
+                         Example: 2 ^ 3 ^ 2 is 2 ^ (3 ^ 2) = 512"));
+        assert_eq!(operator_help("%").as_deref(),
+                   Some("% is the remainder, also written mod.\n\
+                         %: precedence 3, left-associative\nExample: 1 + 7 % 3 is 2"));
+        assert!(operator_help("?").is_none());
+        assert!(operator_help("1 +").is_none());
+    }
+
+    #[test]
+    fn trees_indent_each_level_below_its_operator() {
+        let tree = |input| to_tree(&parse(lex(input)).ok().expect("the input should parse"));
+        assert_eq!(tree("1 + 2 * 3"), "+\n├─ 1\n└─ *\n   ├─ 2\n   └─ 3");
+        assert_eq!(tree("( 1 + 2 ) * 3"), "*\n├─ +\n│  ├─ 1\n│  └─ 2\n└─ 3");
+        assert_eq!(tree("7"), "7");
+    }
+
+    #[test]
+    fn error_codes_are_stable() {
+        use SyntaxError::*;
+        let codes: Vec<&str> = [
+            UnknownSymbol(String::from("?")),
+            MismatchedParentheses,
+            MalformedNumber(String::from("1.2.3")),
+            NumberTooLarge(String::from("300"), IntType::I8),
+            MissingOperand,
+            TooManyOperands,
+            UnexpectedEnd,
+            OperatorDisabled(String::from("/")),
+            GeneralError,
+        ].iter().map(SyntaxError::code).collect();
+        assert_eq!(codes, vec!["E001", "E002", "E003", "E004", "E005",
+                               "E006", "E007", "E008", "E009"]);
+        assert!(matches!(parse(lex("1 + ?")).map_err(|error| error.code()), Err("E001")));
+    }
+
+    #[test]
+    fn redundant_parens_are_those_which_do_not_change_the_parse() {
+        assert_eq!(redundant_parens("( ( 1 ) )"), vec![(0, 8), (2, 6)]);
+        assert!(redundant_parens("( 1 + 2 ) * 3").is_empty());
+        assert_eq!(redundant_parens("1 + ( 2 * 3 )"), vec![(4, 12)]);
+        assert!(redundant_parens("( 1 + 2").is_empty());
+    }
+
+    #[test]
+    fn postfix_from_the_tree_reads_back_as_rpn() {
+        let cases = [("( 1 + 2 ) * 3", "1 2 + 3 *"),
+                     ("1 + 2 * 3 ^ 4", "1 2 3 4 ^ * +"),
+                     ("8 - 3 - 2", "8 3 - 2 -"),
+                     ("2 ^ 3 ^ 2", "2 3 2 ^ ^"),
+                     ("( 1 < 2 ) + 3 !", "1 2 < 3 ! +")];
+        for &(infix, expected) in &cases {
+            let expr = parse(lex(infix)).ok().expect("the infix should parse");
+            let postfix = expr.to_postfix_string();
+            assert_eq!(postfix, expected);
+            let rpn = parse_rpn(lex(&postfix)).ok().expect("the postfix should parse as RPN");
+            assert_eq!(to_sexpr(&rpn), to_sexpr(&expr));
+        }
+    }
+
+    #[test]
+    fn a_misplaced_assignment_is_a_syntax_error() {
+        assert!(matches!(parse(lex("1 + 2 = 3")), Err(SyntaxError::GeneralError)));
+        assert!(matches!(parse(lex("1 = 2 * 3")), Err(SyntaxError::GeneralError)));
+        assert!(matches!(parse(lex("( 1 = 2 )")), Err(SyntaxError::GeneralError)));
+    }
+
+    #[test]
+    fn factorial_needs_the_operand_before_it() {
+        assert!(matches!(parse(lex("3 + ! 4")), Err(SyntaxError::MissingOperand)));
+        assert!(matches!(parse(lex("2 * ! 3")), Err(SyntaxError::MissingOperand)));
+        assert!(matches!(parse(lex("( ! 3 )")), Err(SyntaxError::MissingOperand)));
+        assert!(matches!(parse(lex("max ( ! 3 , 1 )")), Err(SyntaxError::MissingOperand)));
+        assert_eq!(sexpr("( 3 ) ! !"), "(! (! 3))");
+        assert_eq!(sexpr("max ( 1 , 3 ) !"), "(! (max 1 3))");
+    }
+
+    #[test]
+    fn each_argument_is_one_operand_between_commas() {
+        assert!(matches!(parse(lex("max ( , 1 2 )")), Err(SyntaxError::GeneralError)));
+        assert!(matches!(parse(lex("max ( 1 2 , )")), Err(SyntaxError::GeneralError)));
+        assert!(matches!(parse(lex("max ( 1 , )")), Err(SyntaxError::GeneralError)));
+        assert!(matches!(parse(lex("max ( 1 2 )")), Err(SyntaxError::GeneralError)));
+        assert!(matches!(parse(lex("max ( 1 , , 2 )")), Err(SyntaxError::GeneralError)));
+        assert_eq!(sexpr("max ( 1 + 2 , 3 , - 4 )"), "(max (+ 1 2) 3 (- 4))");
+        assert_eq!(sexpr("max ( )"), "(max)");
+    }
+
+    #[test]
+    fn display_parenthesizes_only_where_needed() {
+        let infix = |input| parse(lex(input)).ok().expect("the input should parse").to_string();
+        assert_eq!(infix("( 1 + 2 ) * ( 3 - ( 4 - 5 ) )"), "(1 + 2) * (3 - (4 - 5))");
+        assert_eq!(infix("2 ^ ( 3 ^ 2 ) + ( 2 ^ 3 ) ^ 2"), "2 ^ 3 ^ 2 + (2 ^ 3) ^ 2");
+        assert_eq!(infix("- ( 1 + 2 ) + ( 3 ! ) + ( 1 + 2 ) !"), "- (1 + 2) + 3! + (1 + 2)!");
+        assert_eq!(infix("max ( 1 + 2 , - x ) < 4"), "max(1 + 2, - x) < 4");
+    }
+}
@@ -0,0 +1,346 @@
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::fmt;
+
+/// The most decimal places a float is shown with. An `f64` holds only
+/// about 17 significant digits, so more places add nothing but length.
+pub const MAX_PRECISION: usize = 17;
+
+/// The width of an integer literal with a type suffix, such as `5i8`.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub enum IntType { I8, I16, I32, I64, }
+
+impl IntType {
+    pub fn from_suffix(suffix: &str) -> Option<IntType> {
+        match suffix {
+            "i8" => Some(IntType::I8),
+            "i16" => Some(IntType::I16),
+            "i32" => Some(IntType::I32),
+            "i64" => Some(IntType::I64),
+            _ => None,
+        }
+    }
+
+    /// The number of bits in this width.
+    pub fn bits(self) -> u32 {
+        match self {
+            IntType::I8 => 8,
+            IntType::I16 => 16,
+            IntType::I32 => 32,
+            IntType::I64 => 64,
+        }
+    }
+
+    /// Whether `n` can be represented at this width.
+    pub fn contains(self, n: i64) -> bool {
+        match self {
+            IntType::I8 => i8::try_from(n).is_ok(),
+            IntType::I16 => i16::try_from(n).is_ok(),
+            IntType::I32 => i32::try_from(n).is_ok(),
+            IntType::I64 => true,
+        }
+    }
+}
+
+impl fmt::Display for IntType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            IntType::I8 => write!(f, "i8"),
+            IntType::I16 => write!(f, "i16"),
+            IntType::I32 => write!(f, "i32"),
+            IntType::I64 => write!(f, "i64"),
+        }
+    }
+}
+
+
+/// The names of the base units, in the order of the exponents in `Units`
+/// and in which they are shown.
+const BASE_UNITS: [&str; 3] = ["kg", "m", "s"];
+
+/// The unit of a quantity: the power of each of kilograms, metres and
+/// seconds, so that `[0, 1, -1]` is `m/s`. All zero is dimensionless.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Units(pub [i8; 3]);
+
+impl Units {
+    /// The base unit called `name`, as written after a number.
+    pub fn from_name(name: &str) -> Option<Units> {
+        let index = BASE_UNITS.iter().position(|&unit| unit == name)?;
+        let mut powers = [0; 3];
+        powers[index] = 1;
+        Some(Units(powers))
+    }
+
+    pub fn is_dimensionless(self) -> bool {
+        self.0 == [0; 3]
+    }
+
+    /// The unit of a sum, difference or remainder, for which both units
+    /// must be the same.
+    pub fn same(self, other: Units) -> Option<Units> {
+        Some(self).filter(|&units| units == other)
+    }
+
+    /// The unit of a product, or `None` if a power would overflow.
+    pub fn times(self, other: Units) -> Option<Units> {
+        self.combine(other, i8::checked_add)
+    }
+
+    /// The unit of a quotient, or `None` if a power would overflow.
+    pub fn over(self, other: Units) -> Option<Units> {
+        self.combine(other, i8::checked_sub)
+    }
+
+    /// The unit of a quantity raised to the power `n`.
+    pub fn pow(self, n: i32) -> Option<Units> {
+        let n = i8::try_from(n).ok()?;
+        let mut powers = self.0;
+        for power in powers.iter_mut() {
+            *power = power.checked_mul(n)?;
+        }
+        Some(Units(powers))
+    }
+
+    fn combine(self, other: Units, op: fn(i8, i8) -> Option<i8>) -> Option<Units> {
+        let mut powers = [0; 3];
+        for (i, power) in powers.iter_mut().enumerate() {
+            *power = op(self.0[i], other.0[i])?;
+        }
+        Some(Units(powers))
+    }
+}
+
+impl fmt::Display for Units {
+    /// Show the units with positive powers, then those with negative ones
+    /// after a `/`: `kg m/s^2` or `m/(kg s)`. With no positive powers the
+    /// negative ones are written out, `s^-1`, and dimensionless is `1`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let show = |&(name, power): &(&str, i8)| match power {
+            1 => String::from(name),
+            power => format!("{}^{}", name, power),
+        };
+        let powers: Vec<(&str, i8)> = BASE_UNITS.iter().copied().zip(self.0.iter().copied())
+                                                .filter(|unit| unit.1 != 0)
+                                                .collect();
+        let above: Vec<String> = powers.iter().filter(|unit| unit.1 > 0).map(show).collect();
+        let below: Vec<String> = powers.iter().filter(|unit| unit.1 < 0)
+                                       .map(|&(name, power)| show(&(name, -power)))
+                                       .collect();
+        match below.len() {
+            _ if powers.is_empty() => write!(f, "1"),
+            _ if above.is_empty() =>
+                write!(f, "{}", powers.iter().map(show).collect::<Vec<String>>().join(" ")),
+            0 => write!(f, "{}", above.join(" ")),
+            1 => write!(f, "{}/{}", above.join(" "), below[0]),
+            _ => write!(f, "{}/({})", above.join(" "), below.join(" ")),
+        }
+    }
+}
+
+
+/// The result of evaluating an expression.
+#[derive(Clone, Copy)]
+pub enum Value {
+    Int(i32),
+    /// An integer of an explicitly declared width.
+    Typed(i64, IntType),
+    Float(f64),
+    /// The result of a comparison.
+    Bool(bool),
+    /// `(quotient, remainder, divisor)`, the result of an integer division
+    /// with a remainder under `:quotient remainder`, `:quotient mixed` or
+    /// `:quotient divmod`. Used as an operand, it is just the quotient.
+    Quotient(i32, i32, i32),
+    /// `(remainder, quotient, divisor)`, the result of `%` under
+    /// `:quotient divmod`. Used as an operand, it is just the remainder.
+    Remainder(i32, i32, i32),
+    /// A float with a unit, such as `2.5 m/s`, which is never
+    /// dimensionless: see `Value::quantity`.
+    Quantity(f64, Units),
+}
+
+impl Value {
+    /// `x` in `units`, or just the float `x` if they are dimensionless, as
+    /// in `6 m / 2 m`.
+    pub fn quantity(x: f64, units: Units) -> Value {
+        if units.is_dimensionless() {
+            Value::Float(x)
+        } else {
+            Value::Quantity(x, units)
+        }
+    }
+
+    /// The unit of the value, which is dimensionless unless it is a
+    /// quantity.
+    pub fn units(self) -> Units {
+        match self {
+            Value::Quantity(_, units) => units,
+            _ => Units([0; 3]),
+        }
+    }
+
+    /// The value as a float. Booleans are 1 and 0.
+    pub fn to_f64(self) -> f64 {
+        match self {
+            Value::Int(n) => f64::from(n),
+            Value::Typed(n, _) => n as f64,
+            Value::Float(x) => x,
+            Value::Bool(b) => f64::from(u8::from(b)),
+            Value::Quotient(q, ..) => f64::from(q),
+            Value::Remainder(r, ..) => f64::from(r),
+            Value::Quantity(x, _) => x,
+        }
+    }
+
+    /// The value with any remainder from a division dropped, or for `%`
+    /// any quotient.
+    pub fn truncated(self) -> Value {
+        match self {
+            Value::Quotient(n, ..) | Value::Remainder(n, ..) => Value::Int(n),
+            value => value,
+        }
+    }
+
+    /// Format the value for display. Floats are shown with `precision`
+    /// decimal places if it is set, and otherwise with the fewest digits
+    /// which identify them exactly. A trailing `~` marks a float which was
+    /// rounded, i.e. one whose display is not exactly its value. A
+    /// `precision` beyond `MAX_PRECISION` is taken as `MAX_PRECISION`.
+    ///
+    /// If `scientific` is set, finite floats are shown in scientific
+    /// notation, such as `6.02e23`, and `precision` counts the decimal
+    /// places before the exponent.
+    pub fn display(self, precision: Option<usize>, scientific: bool) -> String {
+        let precision = precision.map(|precision| precision.min(MAX_PRECISION));
+        let shown = match (self, precision) {
+            (Value::Float(x), Some(precision)) if x.is_finite() && scientific =>
+                format!("{:.*e}", precision, x),
+            (Value::Float(x), Some(precision)) if x.is_finite() =>
+                format!("{:.*}", precision, x),
+            (Value::Float(x), None) if x.is_finite() && scientific => format!("{:e}", x),
+            (Value::Quantity(x, units), _) =>
+                return format!("{} {}", Value::Float(x).display(precision, scientific), units),
+            _ => return self.to_string(),
+        };
+        match self {
+            Value::Float(x) if shown.parse() != Ok(x) => shown + "~",
+            _ => shown,
+        }
+    }
+
+    pub fn is_bool(self) -> bool {
+        matches!(self, Value::Bool(_))
+    }
+
+    /// Compare two values, promoting to float if either one is a float.
+    /// Returns `None` if either value is `nan`, if only one of them is a
+    /// boolean, or if their units differ.
+    pub fn compare(self, other: Value) -> Option<Ordering> {
+        if self.units() != other.units() {
+            return None;
+        }
+        match (self.truncated(), other.truncated()) {
+            (Value::Bool(l), Value::Bool(r)) => Some(l.cmp(&r)),
+            (Value::Bool(_), _) | (_, Value::Bool(_)) => None,
+            (Value::Int(l), Value::Int(r)) => Some(l.cmp(&r)),
+            (Value::Int(l), Value::Typed(r, _)) => Some(i64::from(l).cmp(&r)),
+            (Value::Typed(l, _), Value::Int(r)) => Some(l.cmp(&i64::from(r))),
+            (Value::Typed(l, _), Value::Typed(r, _)) => Some(l.cmp(&r)),
+            _ => self.to_f64().partial_cmp(&other.to_f64()),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Value::Int(n) => write!(f, "{}", n),
+            Value::Typed(n, width) => write!(f, "{}{}", n, width),
+            Value::Float(x) if x.is_nan() => write!(f, "nan"),
+            Value::Float(x) => write!(f, "{}", x),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Quotient(n, ..) | Value::Remainder(n, ..) => write!(f, "{}", n),
+            Value::Quantity(x, units) => write!(f, "{} {}", Value::Float(x), units),
+        }
+    }
+}
+
+
+/// Spell out `n` in English, e.g. `-42` as `minus forty-two` and `1203` as
+/// `one thousand two hundred three`.
+pub fn to_words(n: i64) -> String {
+    const SCALES: [&str; 7] =
+        ["", "thousand", "million", "billion", "trillion", "quadrillion", "quintillion"];
+    if n == 0 {
+        return String::from("zero");
+    }
+    // Split the magnitude into groups of three digits, least significant
+    // first, and name each group which is not zero with its scale.
+    let mut groups = Vec::new();
+    let mut rest = n.unsigned_abs();
+    while rest > 0 {
+        groups.push(rest % 1000);
+        rest /= 1000;
+    }
+    let mut words: Vec<String> = groups.iter().zip(SCALES.iter()).rev()
+        .filter(|&(&group, _)| group > 0)
+        .map(|(&group, scale)| match *scale {
+            "" => group_in_words(group),
+            scale => format!("{} {}", group_in_words(group), scale),
+        })
+        .collect();
+    if n < 0 {
+        words.insert(0, String::from("minus"));
+    }
+    words.join(" ")
+}
+
+
+/// Spell out a number from 1 to 999.
+fn group_in_words(n: u64) -> String {
+    const ONES: [&str; 20] =
+        ["", "one", "two", "three", "four", "five", "six", "seven", "eight", "nine", "ten",
+         "eleven", "twelve", "thirteen", "fourteen", "fifteen", "sixteen", "seventeen",
+         "eighteen", "nineteen"];
+    const TENS: [&str; 10] =
+        ["", "", "twenty", "thirty", "forty", "fifty", "sixty", "seventy", "eighty", "ninety"];
+    let (hundreds, rest) = (n / 100, (n % 100) as usize);
+    let rest = match rest {
+        0 => String::new(),
+        1..=19 => String::from(ONES[rest]),
+        _ if rest % 10 == 0 => String::from(TENS[rest / 10]),
+        _ => format!("{}-{}", TENS[rest / 10], ONES[rest % 10]),
+    };
+    match (hundreds, rest.is_empty()) {
+        (0, _) => rest,
+        (_, true) => format!("{} hundred", ONES[hundreds as usize]),
+        (_, false) => format!("{} hundred {}", ONES[hundreds as usize], rest),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numbers_are_spelled_out_in_english() {
+        assert_eq!(to_words(0), "zero");
+        assert_eq!(to_words(-42), "minus forty-two");
+        assert_eq!(to_words(100), "one hundred");
+        assert_eq!(to_words(1_000_000), "one million");
+        assert_eq!(to_words(1203), "one thousand two hundred three");
+        assert_eq!(to_words(i64::MIN),
+                   "minus nine quintillion two hundred twenty-three quadrillion three hundred \
+                    seventy-two trillion thirty-six billion eight hundred fifty-four million \
+                    seven hundred seventy-five thousand eight hundred eight");
+    }
+
+    #[test]
+    fn precision_beyond_the_maximum_is_capped() {
+        assert_eq!(Value::Float(0.5).display(Some(70000), false),
+                   Value::Float(0.5).display(Some(MAX_PRECISION), false));
+        assert_eq!(Value::Float(0.5).display(Some(70000), true), "5.00000000000000000e-1");
+    }
+}
@@ -0,0 +1,137 @@
+use parser::{Comparison, Expr};
+use value::{IntType, Units};
+
+/// A traversal of an expression tree, with one method for each kind of
+/// node. The methods for leaves do nothing by default, and the others
+/// walk the node's children from left to right, so a visitor need only
+/// override the nodes it is interested in.
+///
+/// `walk` is called for every node, so overriding it, and calling
+/// `dispatch` to carry on, sees each node once whatever its kind.
+pub trait ExprVisitor {
+    /// Visit `expr` with the method for its kind of node.
+    fn walk(&mut self, expr: &Expr) {
+        dispatch(self, expr);
+    }
+
+    fn visit_number(&mut self, _n: i32) {}
+    fn visit_float(&mut self, _x: f64) {}
+    fn visit_typed(&mut self, _n: i64, _width: IntType) {}
+    fn visit_quantity(&mut self, _x: f64, _units: Units) {}
+    fn visit_previous(&mut self, _index: usize) {}
+    fn visit_var(&mut self, _name: &str) {}
+    fn visit_memory(&mut self) {}
+
+    fn visit_plus(&mut self, l: &Expr, r: &Expr) {
+        self.walk(l);
+        self.walk(r);
+    }
+
+    fn visit_minus(&mut self, l: &Expr, r: &Expr) {
+        self.walk(l);
+        self.walk(r);
+    }
+
+    fn visit_times(&mut self, l: &Expr, r: &Expr) {
+        self.walk(l);
+        self.walk(r);
+    }
+
+    fn visit_divide(&mut self, l: &Expr, r: &Expr) {
+        self.walk(l);
+        self.walk(r);
+    }
+
+    fn visit_modulo(&mut self, l: &Expr, r: &Expr) {
+        self.walk(l);
+        self.walk(r);
+    }
+
+    fn visit_pow(&mut self, l: &Expr, r: &Expr) {
+        self.walk(l);
+        self.walk(r);
+    }
+
+    fn visit_negate(&mut self, operand: &Expr) {
+        self.walk(operand);
+    }
+
+    fn visit_factorial(&mut self, operand: &Expr) {
+        self.walk(operand);
+    }
+
+    fn visit_compare(&mut self, _comparison: Comparison, l: &Expr, r: &Expr) {
+        self.walk(l);
+        self.walk(r);
+    }
+
+    fn visit_call(&mut self, _name: &str, args: &[Expr]) {
+        for arg in args {
+            self.walk(arg);
+        }
+    }
+}
+
+
+/// Call the method of `visitor` for the kind of node `expr` is.
+pub fn dispatch<V: ExprVisitor + ?Sized>(visitor: &mut V, expr: &Expr) {
+    use Expr::*;
+    match *expr {
+        Number(n) => visitor.visit_number(n),
+        Float(x) => visitor.visit_float(x),
+        Typed(n, width) => visitor.visit_typed(n, width),
+        Quantity(x, units) => visitor.visit_quantity(x, units),
+        Previous(index) => visitor.visit_previous(index),
+        Var(ref name) => visitor.visit_var(name),
+        Memory => visitor.visit_memory(),
+        Plus(ref l, ref r) => visitor.visit_plus(l, r),
+        Minus(ref l, ref r) => visitor.visit_minus(l, r),
+        Times(ref l, ref r) => visitor.visit_times(l, r),
+        Divide(ref l, ref r) => visitor.visit_divide(l, r),
+        Modulo(ref l, ref r) => visitor.visit_modulo(l, r),
+        Pow(ref l, ref r) => visitor.visit_pow(l, r),
+        Negate(ref operand) => visitor.visit_negate(operand),
+        Factorial(ref operand) => visitor.visit_factorial(operand),
+        Compare(comparison, ref l, ref r) => visitor.visit_compare(comparison, l, r),
+        Call(ref name, ref args) => visitor.visit_call(name, args),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lexer::lex;
+    use parser::parse;
+
+    /// Counts every node, and the variables separately.
+    struct Counter {
+        nodes: usize,
+        vars: usize,
+    }
+
+    impl ExprVisitor for Counter {
+        fn walk(&mut self, expr: &Expr) {
+            self.nodes += 1;
+            dispatch(self, expr);
+        }
+
+        fn visit_var(&mut self, _name: &str) {
+            self.vars += 1;
+        }
+    }
+
+    fn count(input: &str) -> (usize, usize) {
+        let expr = parse(lex(input)).ok().expect("the input should parse");
+        let mut counter = Counter { nodes: 0, vars: 0 };
+        counter.walk(&expr);
+        (counter.nodes, counter.vars)
+    }
+
+    #[test]
+    fn a_visitor_sees_every_node_once() {
+        assert_eq!(count("7"), (1, 0));
+        assert_eq!(count("x + y * x"), (5, 3));
+        assert_eq!(count("max ( - x , 2 ! ) < 3"), (7, 1));
+    }
+}
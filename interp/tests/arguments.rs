@@ -0,0 +1,55 @@
+//! Checks that inputs given as command-line arguments are each evaluated,
+//! in one session, with an exit status saying whether all of them ran, and
+//! that `--stdin-once` evaluates just the first line of stdin.
+
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+/// Run the calculator on `args`, with nothing on stdin.
+fn interp(args: &[&str]) -> Output {
+    interp_with_input(args, "")
+}
+
+/// Run the calculator on `args`, with `input` on stdin.
+fn interp_with_input(args: &[&str], input: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_interp"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("the calculator should start");
+    let mut stdin = child.stdin.take().expect("stdin should be piped");
+    // The calculator may exit before reading all of the input.
+    let _ = stdin.write_all(input.as_bytes());
+    drop(stdin);
+    child.wait_with_output().expect("the calculator should run")
+}
+
+#[test]
+fn each_argument_prints_one_result() {
+    let output = interp(&["1 + 2", "3 * 4", "ans + 1"]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "3\n12\n13\n");
+}
+
+#[test]
+fn a_failing_argument_does_not_stop_the_rest() {
+    let output = interp(&["1 + 2", "1 / 0", "2 +", "3 * 4"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(String::from_utf8_lossy(&output.stdout),
+               "3\nCannot divide by zero!\nAn operator is missing an operand.\n12\n");
+}
+
+#[test]
+fn stdin_once_evaluates_only_the_first_line() {
+    let output = interp_with_input(&["--stdin-once"], "1 + 2\n3 * 4\n");
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "3\n");
+    let failed = interp_with_input(&["--stdin-once"], "1 / 0\n3 * 4\n");
+    assert_eq!(failed.status.code(), Some(1));
+    assert_eq!(String::from_utf8_lossy(&failed.stdout), "Cannot divide by zero!\n");
+    let empty = interp_with_input(&["--stdin-once"], "");
+    assert_eq!(empty.status.code(), Some(1));
+    assert_eq!(String::from_utf8_lossy(&empty.stderr), "--stdin-once needs a line of input.\n");
+}
@@ -0,0 +1,34 @@
+//! Checks that the calculator writes each result as soon as it is worked
+//! out, so that a pipe or pager reading from it is not kept waiting for
+//! the rest of the input.
+
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+#[test]
+fn results_reach_a_pipe_before_the_input_ends() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_interp"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("the calculator should start");
+    let mut stdin = child.stdin.take().expect("stdin should be piped");
+    let stdout = child.stdout.take().expect("stdout should be piped");
+    stdin.write_all(b"1 + 2\n").expect("the calculator should read its input");
+    stdin.flush().expect("the calculator should read its input");
+    // Read on another thread, so that output held back in a buffer fails
+    // the test rather than hanging it.
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut line = String::new();
+        let _ = BufReader::new(stdout).read_line(&mut line);
+        let _ = sender.send(line);
+    });
+    let first = receiver.recv_timeout(Duration::from_secs(30));
+    drop(stdin);
+    let _ = child.wait();
+    assert_eq!(first.expect("the result should be written while stdin is still open"), "3\n");
+}